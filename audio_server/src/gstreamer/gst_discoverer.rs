@@ -0,0 +1,70 @@
+use gst::prelude::*;
+use gst_pbutils::prelude::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Properties of a sound file found by probing it.
+#[derive(Clone, Debug, Default)]
+pub struct AudioInfo {
+    pub duration: Option<Duration>,
+    /// Human readable description of the audio codec, such as "Vorbis".
+    pub codec: String,
+    pub container: Option<String>,
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// Bits per second, None when neither the stream nor its tags give it.
+    pub bitrate: Option<u32>,
+    /// Textual tags embedded in the file, such as title and artist.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Probes the file, failing if it has no audio stream that can be decoded.
+pub fn discover(path: &Path) -> Result<AudioInfo, glib::Error> {
+    let uri = glib::filename_to_uri(path, None)?;
+    let discoverer = gst_pbutils::Discoverer::new(gst::ClockTime::from_seconds(5))?;
+    let info = discoverer.discover_uri(&uri)?;
+
+    if info.result() != gst_pbutils::DiscovererResult::Ok {
+        return Err(glib::Error::new(
+            gst::StreamError::Decode,
+            &format!("Discovery failed: {:?}", info.result()),
+        ));
+    }
+    let Some(audio) = info.audio_streams().into_iter().next() else {
+        return Err(glib::Error::new(
+            gst::StreamError::WrongType,
+            "No audio stream found",
+        ));
+    };
+
+    let describe = |caps: Option<gst::Caps>| {
+        caps.map(|caps| gst_pbutils::pb_utils_get_codec_description(&caps).to_string())
+    };
+    let bitrate = match audio.bitrate() {
+        0 => audio.max_bitrate(),
+        bitrate => bitrate,
+    };
+
+    let mut tags = BTreeMap::new();
+    if let Some(tag_list) = info.tags() {
+        for (name, value) in tag_list.iter() {
+            if let Ok(value) = value.get::<String>() {
+                tags.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    Ok(AudioInfo {
+        duration: info.duration().map(|d| Duration::from_nanos(d.nseconds())),
+        codec: describe(audio.caps()).unwrap_or_default(),
+        container: info
+            .stream_info()
+            .filter(|stream| stream.is::<gst_pbutils::DiscovererContainerInfo>())
+            .and_then(|stream| describe(stream.caps())),
+        sample_rate: audio.sample_rate(),
+        channels: audio.channels(),
+        bitrate: (bitrate != 0).then_some(bitrate),
+        tags,
+    })
+}
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub enum GstEvent {
+    Started,
+    Paused,
+    Position {
+        position: Duration,
+        duration: Option<Duration>,
+    },
+    Eos,
+    /// A looping player reached the end of the file and started over.
+    SegmentDone,
+    Error {
+        element: Option<String>,
+        message: String,
+    },
+    RecordingFinalized {
+        size: u64,
+    },
+    /// Part of the file converted by `gst_transcoder::transcode` so far.
+    TranscodeProgress {
+        position: Duration,
+        duration: Option<Duration>,
+    },
+}
+
+/// Called from the bus thread of a pipeline for each event it produces.
+pub type EventCallback = Arc<dyn Fn(GstEvent) + Send + Sync>;
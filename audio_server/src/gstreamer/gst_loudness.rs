@@ -0,0 +1,94 @@
+use crate::gst_player::link_decoded_audio;
+use crate::gst_utils::add_element_by_name;
+use crate::gst_utils::set_pipeline_state;
+use gst::prelude::*;
+use log::debug;
+use std::path::Path;
+
+// Longest time without any measure before the analysis is given up, the decoding being
+// stuck.
+const STALL_TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(10);
+
+/// Integrated loudness of the file in LUFS, measured following EBU R128.
+/// None when the file is too quiet to be measured, silence for instance.
+/// Fails if the ebur128level element, from gst-plugins-rs, is not installed.
+pub fn measure_loudness(path: &Path) -> Result<Option<f64>, glib::Error> {
+    if gst::ElementFactory::find("ebur128level").is_none() {
+        return Err(glib::Error::new(
+            gst::CoreError::MissingPlugin,
+            "ebur128level element not found, gst-plugins-rs audiofx is required",
+        ));
+    }
+
+    let pipeline = gst::Pipeline::new();
+
+    let filesrc = gst::ElementFactory::make("filesrc")
+        .property("location", path.to_string_lossy().as_ref())
+        .build()
+        .expect("failed to create filesrc element");
+    let decodebin = add_element_by_name("decodebin");
+    let queue = add_element_by_name("queue");
+    let audioconvert = add_element_by_name("audioconvert");
+    let audioresample = add_element_by_name("audioresample");
+    // the global loudness is posted along with the momentary one every interval
+    let ebur128level = gst::ElementFactory::make("ebur128level")
+        .property_from_str("mode", "global")
+        .property("interval", gst::ClockTime::from_mseconds(100).nseconds())
+        .build()
+        .expect("failed to create ebur128level element");
+    let fakesink = gst::ElementFactory::make("fakesink")
+        .property("sync", false)
+        .build()
+        .expect("failed to create fakesink element");
+
+    let elements = [
+        &queue,
+        &audioconvert,
+        &audioresample,
+        &ebur128level,
+        &fakesink,
+    ];
+    pipeline.add_many([&filesrc, &decodebin]).unwrap();
+    pipeline.add_many(elements).unwrap();
+    filesrc.link(&decodebin).unwrap();
+    gst::Element::link_many(elements).unwrap();
+    link_decoded_audio(&decodebin, &queue);
+
+    set_pipeline_state(&pipeline, gst::State::Playing);
+
+    let bus = pipeline.bus().unwrap();
+    let mut loudness = None;
+    let result = loop {
+        let Some(message) = bus.timed_pop_filtered(
+            STALL_TIMEOUT,
+            &[
+                gst::MessageType::Eos,
+                gst::MessageType::Error,
+                gst::MessageType::Element,
+            ],
+        ) else {
+            break Err(glib::Error::new(
+                gst::CoreError::Failed,
+                "No loudness measured in time, the decoding is stuck",
+            ));
+        };
+
+        use gst::MessageView;
+        match message.view() {
+            MessageView::Eos(..) => break Ok(loudness.filter(|l: &f64| l.is_finite())),
+            MessageView::Error(err) => break Err(err.error()),
+            MessageView::Element(element) => {
+                if let Some(structure) = element.structure() {
+                    if structure.name() == "ebur128-level" {
+                        loudness = structure.get::<f64>("global-loudness").ok().or(loudness);
+                    }
+                }
+            }
+            _ => {}
+        }
+    };
+
+    set_pipeline_state(&pipeline, gst::State::Null);
+    debug!("loudness of {:?}: {:?}", path, result);
+    result
+}
@@ -0,0 +1,135 @@
+use crate::gst_events::EventCallback;
+use crate::gst_recorder::{RecordingConfig, SampleFormat};
+use crate::gst_utils::add_element_by_name;
+use crate::gst_utils::set_pipeline_state;
+use crate::gst_utils::setup_bus_watch;
+use gst::prelude::*;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamEncoding {
+    /// Interleaved little-endian samples
+    Pcm,
+    /// Opus in an Ogg stream, the chunks being consecutive parts of it
+    Opus,
+}
+
+pub struct AudioChunk {
+    pub data: Vec<u8>,
+    /// Negotiated format of the PCM samples, all unset for Opus.
+    pub config: RecordingConfig,
+}
+
+pub type ChunkCallback = Box<dyn FnMut(AudioChunk) + Send>;
+
+/// Captures the microphone and hands the audio over in chunks as it is recorded.
+pub struct GstMicrophone {
+    pipeline: gst::Pipeline,
+}
+
+impl GstMicrophone {
+    /// `on_chunk` is called from the streaming thread, about every `chunk_duration`.
+    pub fn new(
+        config: RecordingConfig,
+        encoding: StreamEncoding,
+        chunk_duration: Duration,
+        mut on_chunk: ChunkCallback,
+        on_event: Option<EventCallback>,
+    ) -> Self {
+        let pipeline = gst::Pipeline::new();
+
+        // PCM samples are sent in a format the client knows about
+        let mut config = config;
+        if encoding == StreamEncoding::Pcm {
+            config.sample_format.get_or_insert(SampleFormat::S16);
+        }
+
+        let autoaudiosrc = add_element_by_name("autoaudiosrc");
+        let queue = add_element_by_name("queue");
+        let audioconvert = add_element_by_name("audioconvert");
+        let audioresample = add_element_by_name("audioresample");
+        let capsfilter = gst::ElementFactory::make("capsfilter")
+            .property("caps", config.caps())
+            .build()
+            .expect("failed to create capsfilter element");
+
+        let mut elements = vec![autoaudiosrc, queue, audioconvert, audioresample, capsfilter];
+        let chunk_time = gst::ClockTime::from_nseconds(chunk_duration.as_nanos() as u64);
+        if encoding == StreamEncoding::Opus {
+            // pages are flushed every chunk, whatever their size
+            let oggmux = gst::ElementFactory::make("oggmux")
+                .property("max-delay", chunk_time.nseconds())
+                .property("max-page-delay", chunk_time.nseconds())
+                .build()
+                .expect("failed to create oggmux element");
+            elements.push(add_element_by_name("opusenc"));
+            elements.push(oggmux);
+        }
+
+        let mut data = Vec::new();
+        let mut duration = gst::ClockTime::ZERO;
+        let appsink = gst_app::AppSink::builder()
+            .sync(false)
+            .callbacks(
+                gst_app::AppSinkCallbacks::builder()
+                    .new_sample(move |appsink| {
+                        let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                        let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
+                        let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+                        data.extend_from_slice(&map);
+                        duration += buffer.duration().unwrap_or(gst::ClockTime::ZERO);
+
+                        if encoding == StreamEncoding::Opus || duration >= chunk_time {
+                            let config = match encoding {
+                                StreamEncoding::Pcm => pcm_config(sample.caps()),
+                                StreamEncoding::Opus => RecordingConfig::default(),
+                            };
+                            on_chunk(AudioChunk {
+                                data: std::mem::take(&mut data),
+                                config,
+                            });
+                            duration = gst::ClockTime::ZERO;
+                        }
+                        Ok(gst::FlowSuccess::Ok)
+                    })
+                    .build(),
+            )
+            .build();
+        elements.push(appsink.upcast());
+
+        pipeline.add_many(&elements).unwrap();
+        gst::Element::link_many(&elements).unwrap();
+
+        setup_bus_watch(&pipeline, on_event);
+
+        Self { pipeline }
+    }
+
+    pub fn start(&self) {
+        set_pipeline_state(&self.pipeline, gst::State::Playing);
+    }
+
+    pub fn stop(&self) {
+        set_pipeline_state(&self.pipeline, gst::State::Null);
+    }
+}
+
+impl Drop for GstMicrophone {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn pcm_config(caps: Option<&gst::CapsRef>) -> RecordingConfig {
+    let Some(structure) = caps.and_then(|caps| caps.structure(0)) else {
+        return RecordingConfig::default();
+    };
+    RecordingConfig {
+        rate: structure.get::<i32>("rate").ok().map(|r| r as u32),
+        channels: structure.get::<i32>("channels").ok().map(|c| c as u32),
+        sample_format: structure
+            .get::<&str>("format")
+            .ok()
+            .and_then(SampleFormat::from_caps_format),
+    }
+}
@@ -0,0 +1,137 @@
+use crate::gst_events::{EventCallback, GstEvent};
+use crate::gst_utils::add_element_by_name;
+use crate::gst_utils::set_pipeline_state;
+use crate::gst_utils::setup_bus_watch;
+use gst::prelude::*;
+use std::sync::Arc;
+
+/// Output pipeline summing the sounds of every attached GstPlayer into a single sink.
+#[derive(Clone)]
+pub struct GstMixer {
+    pipeline: gst::Pipeline,
+    mixer: gst::Element,
+    volume: gst::Element,
+}
+
+impl Default for GstMixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GstMixer {
+    pub fn new() -> Self {
+        Self::with_events(None)
+    }
+
+    /// Only errors are reported through `on_event`, playback events come from the players.
+    pub fn with_events(on_event: Option<EventCallback>) -> Self {
+        let pipeline = gst::Pipeline::new();
+
+        // A live silent input keeps the mixer running when nothing is played. It also
+        // makes the mixer live, so that it does not wait for paused or ended players.
+        let silence = gst::ElementFactory::make("audiotestsrc")
+            .property_from_str("wave", "silence")
+            .property("is-live", true)
+            .build()
+            .expect("failed to create audiotestsrc element");
+        let mixer = add_element_by_name("audiomixer");
+        let capsfilter = gst::ElementFactory::make("capsfilter")
+            .property(
+                "caps",
+                gst::Caps::builder("audio/x-raw")
+                    .field("rate", 48000i32)
+                    .field("channels", 2i32)
+                    .build(),
+            )
+            .build()
+            .expect("failed to create capsfilter element");
+        let convert = add_element_by_name("audioconvert");
+        let resample = add_element_by_name("audioresample");
+        let volume = add_element_by_name("volume");
+        let sink = add_element_by_name("autoaudiosink");
+
+        let elements = &[
+            &silence,
+            &mixer,
+            &capsfilter,
+            &convert,
+            &resample,
+            &volume,
+            &sink,
+        ];
+        pipeline.add_many(elements).unwrap();
+        gst::Element::link_many(elements).unwrap();
+
+        let on_error = on_event.map(|on_event| -> EventCallback {
+            Arc::new(move |event| {
+                if let GstEvent::Error { .. } = event {
+                    on_event(event);
+                }
+            })
+        });
+        setup_bus_watch(&pipeline, on_error);
+
+        set_pipeline_state(&pipeline, gst::State::Playing);
+
+        Self {
+            pipeline,
+            mixer,
+            volume,
+        }
+    }
+
+    /// Adds a player branch to the pipeline and links its "src" pad to a new mixer input.
+    pub(crate) fn attach(&self, bin: &gst::Bin) -> gst::Pad {
+        self.pipeline.add(bin).unwrap();
+        let mixer_pad = self
+            .mixer
+            .request_pad_simple("sink_%u")
+            .expect("failed to request audiomixer pad");
+        bin.static_pad("src")
+            .expect("player bin has no src pad")
+            .link(&mixer_pad)
+            .unwrap();
+        mixer_pad
+    }
+
+    pub(crate) fn detach(&self, bin: &gst::Bin, mixer_pad: &gst::Pad) {
+        let _ = bin.set_state(gst::State::Null);
+        if let Some(src_pad) = bin.static_pad("src") {
+            let _ = src_pad.unlink(mixer_pad);
+        }
+        self.mixer.release_request_pad(mixer_pad);
+        let _ = self.pipeline.remove(bin);
+    }
+
+    /// Offsets a mixer input so that data at `running_time` in the player branch
+    /// is mixed now.
+    pub(crate) fn schedule_input(&self, mixer_pad: &gst::Pad, running_time: gst::ClockTime) {
+        let now = self
+            .pipeline
+            .current_running_time()
+            .unwrap_or(gst::ClockTime::ZERO);
+        mixer_pad.set_offset(now.nseconds() as i64 - running_time.nseconds() as i64);
+    }
+
+    /// Master gain applied to the mix, 1.0 being the original level.
+    pub fn set_volume(&self, volume: f64) {
+        self.volume.set_property("volume", volume);
+    }
+
+    pub fn get_volume(&self) -> f64 {
+        self.volume.property::<f64>("volume")
+    }
+
+    pub fn mute(&self, mute: bool) {
+        self.volume.set_property("mute", mute);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.volume.property::<bool>("mute")
+    }
+
+    pub fn stop(&self) {
+        set_pipeline_state(&self.pipeline, gst::State::Null);
+    }
+}
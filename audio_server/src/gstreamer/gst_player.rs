@@ -1,99 +1,625 @@
 // based on https://gitlab.freedesktop.org/gstreamer/gstreamer-rs/-/blob/main/examples/src/bin/decodebin.rs?ref_type=heads
 
-use crate::gst_utils::set_pipeline_state;
-use crate::gst_utils::setup_bus_watch;
+use crate::gst_events::{EventCallback, GstEvent};
+use crate::gst_mixer::GstMixer;
+use crate::gst_recorder::{RecordingConfig, SampleFormat};
+use crate::gst_utils::add_element_by_name;
+use crate::gst_utils::spawn_event_thread;
 use gst::{element_warning, prelude::*};
-use log::error;
+use gst_controller::prelude::*;
+use gst_controller::{DirectControlBinding, InterpolationControlSource, InterpolationMode};
+use log::{debug, error, warn};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+// Running time of the buffer held by the blocking probe, None until a buffer reaches it.
+type BlockedBuffer = Arc<(Mutex<Option<gst::ClockTime>>, Condvar)>;
+
+/// Volume ramps applied when a player starts and when it is stopped with `fade_out`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Fades {
+    pub fade_in: Duration,
+    pub fade_out: Duration,
+}
+
+/// Feeds a player created with `GstPlayer::with_stream`.
+#[derive(Clone)]
+pub struct StreamSource {
+    appsrc: gst_app::AppSrc,
+}
+
+impl StreamSource {
+    /// Blocks while the player has enough data waiting, returns false once it is stopped.
+    pub fn push(&self, data: &[u8]) -> bool {
+        self.appsrc
+            .push_buffer(gst::Buffer::from_slice(data.to_vec()))
+            .is_ok()
+    }
+
+    /// The player ends once the data already pushed is played.
+    pub fn end(&self) {
+        let _ = self.appsrc.end_of_stream();
+    }
+}
 
 pub struct GstPlayer {
-    pipeline: gst::Pipeline,
+    mixer: GstMixer,
+    // true when the mixer was created for this player only
+    own_mixer: bool,
+    bin: gst::Bin,
+    // None once the player is stopped
+    mixer_pad: Option<gst::Pad>,
+    volume: gst::Element,
+    // loudness correction of the file, kept apart from the user volume as well
+    gain: gst::Element,
+    // gain driven by the fade control source, kept apart from the user volume
+    fade: gst::Element,
+    fade_control: InterpolationControlSource,
+    fades: Fades,
+    fading_out: Arc<AtomicBool>,
+    // stream time of the last buffer that went through the fade element, in ns
+    stream_time: Arc<AtomicU64>,
+    // number of plays left, None when looping forever
+    remaining_loops: Arc<Mutex<Option<u32>>>,
+    on_event: Option<EventCallback>,
+    block_probe: Option<gst::PadProbeId>,
+    blocked_buffer: BlockedBuffer,
+    playing: bool,
 }
 
 impl GstPlayer {
     pub fn new(path: &str) -> Self {
-        let pipeline = gst::Pipeline::new();
+        Self::with_events(path, None)
+    }
 
+    /// Plays the file through a mixer of its own.
+    pub fn with_events(path: &str, on_event: Option<EventCallback>) -> Self {
+        let mixer = GstMixer::with_events(on_event.clone());
+        let mut player = Self::with_mixer(&mixer, path, on_event);
+        player.own_mixer = true;
+        player
+    }
+
+    /// Plays the file through `mixer`, along with the other players attached to it.
+    pub fn with_mixer(mixer: &GstMixer, path: &str, on_event: Option<EventCallback>) -> Self {
         let filesrc = gst::ElementFactory::make("filesrc")
             .property("location", path)
             .build()
             .expect("failed to create filesrc element");
 
-        let decodebin = gst::ElementFactory::make("decodebin")
-            .build()
-            .expect("failed to create decodebin element");
+        Self::with_source(mixer, &[&filesrc], true, on_event)
+    }
+
+    /// Plays the data pushed to the returned source through `mixer`. `pcm` gives the
+    /// format of raw samples, S16 by default, the data is decoded when it is None.
+    /// Started with `play_stream`.
+    pub fn with_stream(
+        mixer: &GstMixer,
+        pcm: Option<RecordingConfig>,
+        on_event: Option<EventCallback>,
+    ) -> (Self, StreamSource) {
+        // pushing blocks while this much data is waiting to be played
+        let appsrc = gst_app::AppSrc::builder()
+            .format(gst::Format::Bytes)
+            .block(true)
+            .max_bytes(64 * 1024)
+            .build();
+
+        let player = match pcm {
+            Some(mut config) => {
+                config.sample_format.get_or_insert(SampleFormat::S16);
+                let mut caps = config.caps();
+                caps.make_mut().set("layout", "interleaved");
+                appsrc.set_caps(Some(&caps));
+
+                // timestamps the samples from their format
+                let parse = gst::ElementFactory::make("rawaudioparse")
+                    .property("use-sink-caps", true)
+                    .build()
+                    .expect("failed to create rawaudioparse element");
+                Self::with_source(
+                    mixer,
+                    &[appsrc.upcast_ref::<gst::Element>(), &parse],
+                    false,
+                    on_event,
+                )
+            }
+            None => Self::with_source(
+                mixer,
+                &[appsrc.upcast_ref::<gst::Element>()],
+                true,
+                on_event,
+            ),
+        };
+
+        (player, StreamSource { appsrc })
+    }
+
+    /// Builds the branch from the `source` elements, followed by a decodebin if `decode`.
+    fn with_source(
+        mixer: &GstMixer,
+        source: &[&gst::Element],
+        decode: bool,
+        on_event: Option<EventCallback>,
+    ) -> Self {
+        let bin = gst::Bin::new();
+
+        bin.add_many(source).unwrap();
+        gst::Element::link_many(source).unwrap();
+
+        // The output branch is built upfront so that the volume can be set
+        // before decodebin exposes its pads.
+        let queue = add_element_by_name("queue");
+        let convert = add_element_by_name("audioconvert");
+        let resample = add_element_by_name("audioresample");
+        let gain = add_element_by_name("volume");
+        let fade = add_element_by_name("volume");
+        let volume = add_element_by_name("volume");
 
-        let elements = &[&filesrc, &decodebin];
-        pipeline.add_many(elements).unwrap();
+        let elements = &[&queue, &convert, &resample, &gain, &fade, &volume];
+        bin.add_many(elements).unwrap();
         gst::Element::link_many(elements).unwrap();
 
-        let pipeline_weak = pipeline.downgrade();
+        let volume_src = volume.static_pad("src").expect("volume has no srcpad");
+        let src_pad = gst::GhostPad::builder_with_target(&volume_src)
+            .unwrap()
+            .name("src")
+            .build();
+        bin.add_pad(&src_pad).unwrap();
 
-        decodebin.connect_pad_added(move |dbin, src_pad| {
-            let Some(pipeline) = pipeline_weak.upgrade() else {
-                return;
-            };
-
-            let (is_audio, is_video) = {
-                let media_type = src_pad.current_caps().and_then(|caps| {
-                    caps.structure(0).map(|s| {
-                        let name = s.name();
-                        (name.starts_with("audio/"), name.starts_with("video/"))
-                    })
-                });
-
-                match media_type {
-                    None => {
-                        element_warning!(
-                            dbin,
-                            gst::CoreError::Negotiation,
-                            ("Failed to get media type from pad {}", src_pad.name())
-                        );
-
-                        return;
-                    }
-                    Some(media_type) => media_type,
-                }
-            };
+        let last = *source.last().expect("player has no source");
+        if decode {
+            let decodebin = gst::ElementFactory::make("decodebin")
+                .build()
+                .expect("failed to create decodebin element");
+            bin.add(&decodebin).unwrap();
+            last.link(&decodebin).unwrap();
+            link_decoded_audio(&decodebin, &queue);
+        } else {
+            last.link(&queue).unwrap();
+        }
 
-            if is_audio {
-                let queue = gst::ElementFactory::make("queue")
-                    .build()
-                    .expect("failed to create queue element");
-                let convert = gst::ElementFactory::make("audioconvert")
-                    .build()
-                    .expect("failed to create audioconvert element");
-                let resample = gst::ElementFactory::make("audioresample")
-                    .build()
-                    .expect("failed to create audioresample element");
-                let sink = gst::ElementFactory::make("autoaudiosink")
-                    .build()
-                    .expect("failed to create audioautosink element");
+        // The control points are in stream time, as the element processes the buffers.
+        let fade_control = InterpolationControlSource::new();
+        fade_control.set_mode(InterpolationMode::Linear);
+        let binding = DirectControlBinding::new_absolute(&fade, "volume", &fade_control);
+        fade.add_control_binding(&binding).unwrap();
 
-                let elements = &[&queue, &convert, &resample, &sink];
-                pipeline.add_many(elements).unwrap();
-                gst::Element::link_many(elements).unwrap();
+        let stream_time = Arc::new(AtomicU64::new(0));
+        let stream_time_ref = Arc::clone(&stream_time);
+        fade.static_pad("sink")
+            .expect("volume has no sinkpad")
+            .add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                    let time = pad
+                        .sticky_event::<gst::event::Segment>(0)
+                        .and_then(|event| {
+                            event
+                                .segment()
+                                .downcast_ref::<gst::ClockTime>()
+                                .and_then(|segment| segment.to_stream_time(buffer.pts()))
+                        });
+                    if let Some(time) = time {
+                        stream_time_ref.store(time.nseconds(), Ordering::Relaxed);
+                    }
+                }
+                gst::PadProbeReturn::Ok
+            });
 
-                for e in elements {
-                    e.sync_state_with_parent().unwrap();
+        let remaining_loops = Arc::new(Mutex::new(Some(1)));
+        let loops_ref = Arc::clone(&remaining_loops);
+        let fading_out = Arc::new(AtomicBool::new(false));
+        let fading_out_ref = Arc::clone(&fading_out);
+        let fade_control_ref = fade_control.clone();
+        let fade_weak = fade.downgrade();
+        let volume_weak = volume.downgrade();
+        let forward = on_event.clone();
+        let handle_event: EventCallback = Arc::new(move |event| {
+            if let GstEvent::SegmentDone = event {
+                if let Some(fade) = fade_weak.upgrade() {
+                    reset_fade(&fade, &fade_control_ref, &fading_out_ref);
+                }
+                if let Some(volume) = volume_weak.upgrade() {
+                    restart_segment(&volume, &loops_ref);
                 }
+            }
+            if let Some(on_event) = forward.as_ref() {
+                on_event(event);
+            }
+        });
 
-                let sink_pad = queue.static_pad("sink").expect("queue has no sinkpad");
-                src_pad.link(&sink_pad).unwrap();
-            } else if is_video {
-                error!("Video stream detected. This player only supports audio streams.");
+        // The mixer never ends, so the end of this player is caught on its own branch.
+        // Events are handled out of the streaming thread, as seeking from it may deadlock.
+        let stream_events = spawn_event_thread(handle_event);
+        volume_src.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_pad, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                let event = match event.view() {
+                    gst::EventView::Eos(..) => Some(GstEvent::Eos),
+                    gst::EventView::SegmentDone(..) => Some(GstEvent::SegmentDone),
+                    _ => None,
+                };
+                if let Some(event) = event {
+                    let _ = stream_events.send(event);
+                }
             }
+            gst::PadProbeReturn::Ok
         });
 
-        setup_bus_watch(&pipeline);
+        let mixer_pad = mixer.attach(&bin);
+
+        Self {
+            mixer: mixer.clone(),
+            own_mixer: false,
+            bin,
+            mixer_pad: Some(mixer_pad),
+            volume,
+            gain,
+            fade,
+            fade_control,
+            fades: Fades::default(),
+            fading_out,
+            stream_time,
+            remaining_loops,
+            on_event,
+            block_probe: None,
+            blocked_buffer: Arc::new((Mutex::new(None), Condvar::new())),
+            playing: false,
+        }
+    }
+
+    /// Number of times the file is played, None to loop until stopped.
+    /// Must be called before `play`.
+    pub fn set_loops(&mut self, loops: Option<u32>) {
+        *self.remaining_loops.lock().unwrap() = loops;
+    }
+
+    /// Must be called before `play`.
+    pub fn set_fades(&mut self, fades: Fades) {
+        self.fades = fades;
+    }
+
+    fn is_looping(&self) -> bool {
+        *self.remaining_loops.lock().unwrap() != Some(1)
+    }
+
+    /// Returns false if the player could not start, the file not being decodable for
+    /// instance. An error event is reported and the player is stopped in that case.
+    pub fn play(&mut self) -> bool {
+        self.start_branch() && self.finish_start()
+    }
+
+    /// Starts a player created with `with_stream`. The first chunk has to hold enough
+    /// data to decode a buffer from it. Returns false as `play` does.
+    pub fn play_stream(&mut self, source: &StreamSource, first_chunk: &[u8]) -> bool {
+        if !self.start_branch() {
+            return false;
+        }
+        // the source accepts data once the branch is running
+        source.push(first_chunk);
+        self.finish_start()
+    }
+
+    fn start_branch(&mut self) -> bool {
+        if self.mixer_pad.is_none() {
+            return false;
+        }
+        if !self.fades.fade_in.is_zero() {
+            self.fade_control.unset_all();
+            self.fade_control.set(gst::ClockTime::ZERO, 0.0);
+            self.fade_control
+                .set(to_clock_time(self.fades.fade_in), 1.0);
+        }
+        // The branch is held until its first buffer is decoded, so that it can be
+        // scheduled in the mix from that buffer.
+        self.block();
+        if let Err(err) = self.bin.sync_state_with_parent() {
+            self.fail(format!("Failed to start player: {:?}", err));
+            return false;
+        }
+        true
+    }
+
+    fn finish_start(&mut self) -> bool {
+        if self.wait_blocked().is_none() {
+            self.fail("Failed to start player: no data decoded".to_string());
+            return false;
+        }
+
+        if self.is_looping() {
+            // At the end of each segment a SEGMENT_DONE event is sent instead of EOS, and
+            // the playback restarts without flushing so there is no gap between iterations.
+            self.seek_and_wait(
+                gst::SeekFlags::FLUSH | gst::SeekFlags::SEGMENT,
+                gst::ClockTime::ZERO,
+            );
+        }
+        self.unblock();
+        self.set_playing(true);
+        true
+    }
+
+    /// Stops a player that could not start, reporting why through its events.
+    fn fail(&mut self, message: String) {
+        error!("{}", message);
+        self.stop();
+        if let Some(on_event) = self.on_event.as_ref() {
+            on_event(GstEvent::Error {
+                element: None,
+                message,
+            });
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if self.playing {
+            self.block();
+            self.set_playing(false);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if !self.playing && self.block_probe.is_some() {
+            self.wait_blocked();
+            self.unblock();
+            self.set_playing(true);
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn position(&self) -> Option<Duration> {
+        self.volume
+            .query_position::<gst::ClockTime>()
+            .map(|t| Duration::from_nanos(t.nseconds()))
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.volume
+            .query_duration::<gst::ClockTime>()
+            .map(|t| Duration::from_nanos(t.nseconds()))
+    }
+
+    pub fn seek(&mut self, position: Duration) {
+        let position = to_clock_time(position);
+        let mut flags = gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT;
+        if self.is_looping() {
+            flags |= gst::SeekFlags::SEGMENT;
+        }
+
+        // A flushing seek restarts the running time of the branch, so it has to be
+        // scheduled again in the mix. A paused player stays blocked.
+        let paused = self.block_probe.is_some();
+        if !paused {
+            self.block();
+        }
+        if !self.seek_and_wait(flags, position) {
+            error!("Failed to seek to {}", position);
+        }
+        if !paused {
+            self.unblock();
+        }
+    }
+
+    /// Linear gain applied to the output, 1.0 being the original level.
+    pub fn set_volume(&mut self, volume: f64) {
+        self.volume.set_property("volume", volume);
+    }
+
+    pub fn get_volume(&self) -> f64 {
+        self.volume.property::<f64>("volume")
+    }
+
+    /// Linear gain correcting the loudness of the file, applied on top of the volume.
+    pub fn set_gain(&mut self, gain: f64) {
+        self.gain.set_property("volume", gain.clamp(0.0, 10.0));
+    }
 
-        Self { pipeline }
+    pub fn mute(&mut self, mute: bool) {
+        self.volume.set_property("mute", mute);
     }
 
-    pub fn play(&mut self) {
-        set_pipeline_state(&self.pipeline, gst::State::Playing);
+    /// Lowers the output to silence over the configured fade out, returns how long
+    /// it lasts. The player keeps running and has to be stopped once silent.
+    pub fn fade_out(&mut self) -> Duration {
+        if !self.playing || self.fades.fade_out.is_zero() {
+            return Duration::ZERO;
+        }
+        self.fading_out.store(true, Ordering::Relaxed);
+
+        let start = gst::ClockTime::from_nseconds(self.stream_time.load(Ordering::Relaxed));
+        let from = self.fade.property::<f64>("volume");
+        self.fade_control.unset_all();
+        self.fade_control.set(start, from);
+        self.fade_control
+            .set(start + to_clock_time(self.fades.fade_out), 0.0);
+        self.fades.fade_out
     }
 
+    /// Stops right away, see `fade_out` to avoid a pop on the speaker.
     pub fn stop(&mut self) {
-        set_pipeline_state(&self.pipeline, gst::State::Null);
+        let Some(mixer_pad) = self.mixer_pad.take() else {
+            return;
+        };
+        self.mixer.detach(&self.bin, &mixer_pad);
+        if self.own_mixer {
+            self.mixer.stop();
+        }
+        self.block_probe = None;
+        self.playing = false;
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+        if let Some(on_event) = self.on_event.as_ref() {
+            on_event(if playing {
+                GstEvent::Started
+            } else {
+                GstEvent::Paused
+            });
+        }
+    }
+
+    /// Holds the buffers of the branch until `unblock` is called.
+    fn block(&mut self) {
+        if self.block_probe.is_some() {
+            return;
+        }
+        *self.blocked_buffer.0.lock().unwrap() = None;
+        let blocked_buffer = Arc::clone(&self.blocked_buffer);
+        self.block_probe = self.volume.static_pad("src").unwrap().add_probe(
+            gst::PadProbeType::BLOCK | gst::PadProbeType::BUFFER,
+            move |pad, info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                    let running_time = pad
+                        .sticky_event::<gst::event::Segment>(0)
+                        .and_then(|event| {
+                            event
+                                .segment()
+                                .downcast_ref::<gst::ClockTime>()
+                                .and_then(|segment| segment.to_running_time(buffer.pts()))
+                        })
+                        .unwrap_or(gst::ClockTime::ZERO);
+                    let (lock, cvar) = &*blocked_buffer;
+                    *lock.lock().unwrap() = Some(running_time);
+                    cvar.notify_all();
+                }
+                gst::PadProbeReturn::Ok
+            },
+        );
+    }
+
+    fn wait_blocked(&self) -> Option<gst::ClockTime> {
+        let (lock, cvar) = &*self.blocked_buffer;
+        let (running_time, timeout) = cvar
+            .wait_timeout_while(lock.lock().unwrap(), Duration::from_secs(5), |t| {
+                t.is_none()
+            })
+            .unwrap();
+        if timeout.timed_out() {
+            warn!("No data received from the player");
+        }
+        *running_time
+    }
+
+    /// Releases the held buffers, the first one being mixed right away.
+    fn unblock(&mut self) {
+        let Some(probe) = self.block_probe.take() else {
+            return;
+        };
+        let running_time = *self.blocked_buffer.0.lock().unwrap();
+        if let (Some(mixer_pad), Some(running_time)) = (self.mixer_pad.as_ref(), running_time) {
+            self.mixer.schedule_input(mixer_pad, running_time);
+        }
+        self.volume.static_pad("src").unwrap().remove_probe(probe);
+    }
+
+    fn seek_and_wait(&mut self, flags: gst::SeekFlags, position: gst::ClockTime) -> bool {
+        *self.blocked_buffer.0.lock().unwrap() = None;
+        if !send_seek(&self.volume, flags, position) {
+            return false;
+        }
+        self.wait_blocked().is_some()
+    }
+}
+
+impl Drop for GstPlayer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Links the first audio stream exposed by `decodebin` to `queue`.
+pub(crate) fn link_decoded_audio(decodebin: &gst::Element, queue: &gst::Element) {
+    let queue = queue.clone();
+    decodebin.connect_pad_added(move |dbin, src_pad| {
+        let (is_audio, is_video) = {
+            let media_type = src_pad.current_caps().and_then(|caps| {
+                caps.structure(0).map(|s| {
+                    let name = s.name();
+                    (name.starts_with("audio/"), name.starts_with("video/"))
+                })
+            });
+
+            match media_type {
+                None => {
+                    element_warning!(
+                        dbin,
+                        gst::CoreError::Negotiation,
+                        ("Failed to get media type from pad {}", src_pad.name())
+                    );
+
+                    return;
+                }
+                Some(media_type) => media_type,
+            }
+        };
+
+        if is_audio {
+            let sink_pad = queue.static_pad("sink").expect("queue has no sinkpad");
+            if sink_pad.is_linked() {
+                error!("Multiple audio streams detected. Only the first one is played.");
+                return;
+            }
+            src_pad.link(&sink_pad).unwrap();
+        } else if is_video {
+            error!("Video stream detected. This player only supports audio streams.");
+        }
+    });
+}
+
+fn to_clock_time(duration: Duration) -> gst::ClockTime {
+    gst::ClockTime::from_nseconds(duration.as_nanos() as u64)
+}
+
+fn reset_fade(
+    fade: &gst::Element,
+    fade_control: &InterpolationControlSource,
+    fading_out: &AtomicBool,
+) {
+    // the stream time restarts with each loop, leaving the fade points behind
+    fade_control.unset_all();
+    let gain = if fading_out.load(Ordering::Relaxed) {
+        0.0
+    } else {
+        1.0
+    };
+    fade.set_property("volume", gain);
+}
+
+fn send_seek(volume: &gst::Element, flags: gst::SeekFlags, position: gst::ClockTime) -> bool {
+    let seek = gst::event::Seek::new(
+        1.0,
+        flags,
+        gst::SeekType::Set,
+        position,
+        gst::SeekType::None,
+        gst::ClockTime::NONE,
+    );
+    // sent upstream from the volume element so that only this player's branch seeks,
+    // not the whole mixer pipeline
+    volume.static_pad("sink").unwrap().push_event(seek)
+}
+
+fn restart_segment(volume: &gst::Element, remaining_loops: &Mutex<Option<u32>>) {
+    let mut remaining_loops = remaining_loops.lock().unwrap();
+    if let Some(remaining) = remaining_loops.as_mut() {
+        *remaining = remaining.saturating_sub(1);
+        if *remaining == 0 {
+            return;
+        }
+    }
+    debug!("Looping, {:?} plays left", *remaining_loops);
+
+    // the last iteration is played without the SEGMENT flag so that it ends with EOS
+    let flags = if *remaining_loops == Some(1) {
+        gst::SeekFlags::empty()
+    } else {
+        gst::SeekFlags::SEGMENT
+    };
+    if !send_seek(volume, flags, gst::ClockTime::ZERO) {
+        error!("Failed to loop playback");
     }
 }
@@ -1,50 +1,197 @@
+use crate::gst_events::{EventCallback, GstEvent};
 use crate::gst_utils::add_element_by_name;
 use crate::gst_utils::set_pipeline_state;
 use crate::gst_utils::setup_bus_watch;
 use gst::prelude::*;
-use log::debug;
+use log::{debug, warn};
+use std::fs;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
+// Set once the end of stream is posted on the bus, the file being complete.
+type EosReached = Arc<(Mutex<bool>, Condvar)>;
+
+// Longest wait for the encoder to complete the file when the recording stops.
+const EOS_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingFormat {
+    Wav,
+    Flac,
+    /// Opus in an Ogg container
+    Opus,
+    Mp3,
+}
+
+impl RecordingFormat {
+    /// Format matching the extension of `path`, if any.
+    pub fn from_path(path: &str) -> Option<Self> {
+        Self::from_extension(Path::new(path).extension()?.to_str()?)
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "wav" => Some(Self::Wav),
+            "flac" => Some(Self::Flac),
+            "ogg" | "opus" => Some(Self::Opus),
+            "mp3" => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+
+    /// Extension of the files written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+            Self::Opus => "ogg",
+            Self::Mp3 => "mp3",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    S16,
+    S24,
+    S32,
+    F32,
+}
+
+impl SampleFormat {
+    fn caps_format(self) -> &'static str {
+        match self {
+            Self::S16 => "S16LE",
+            Self::S24 => "S24LE",
+            Self::S32 => "S32LE",
+            Self::F32 => "F32LE",
+        }
+    }
+
+    pub(crate) fn from_caps_format(format: &str) -> Option<Self> {
+        match format {
+            "S16LE" => Some(Self::S16),
+            "S24LE" => Some(Self::S24),
+            "S32LE" => Some(Self::S32),
+            "F32LE" => Some(Self::F32),
+            _ => None,
+        }
+    }
+}
+
+/// Raw audio fed to the encoder, whatever the microphone provides. The unset fields
+/// are negotiated with the encoder, which has to support the set ones.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecordingConfig {
+    pub rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub sample_format: Option<SampleFormat>,
+}
+
+impl RecordingConfig {
+    pub(crate) fn caps(&self) -> gst::Caps {
+        let mut caps = gst::Caps::builder("audio/x-raw");
+        if let Some(rate) = self.rate {
+            caps = caps.field("rate", rate as i32);
+        }
+        if let Some(channels) = self.channels {
+            caps = caps.field("channels", channels as i32);
+        }
+        if let Some(sample_format) = self.sample_format {
+            caps = caps.field("format", sample_format.caps_format());
+        }
+        caps.build()
+    }
+}
+
+/// Encoder settings, the encoder defaults being used for the unset ones.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecordingOptions {
+    /// Picked from the file extension if unset, Opus if the extension is unknown.
+    pub format: Option<RecordingFormat>,
+    /// Target bitrate in kbit/s for Opus and MP3. MP3 is then encoded at a constant bitrate.
+    pub bitrate: Option<u32>,
+    /// Variable bitrate quality of MP3, from 0.0 (smallest file) to 1.0 (best).
+    pub quality: Option<f32>,
+    pub config: RecordingConfig,
+}
+
 pub struct GstRecorder {
     pipeline: gst::Pipeline,
+    path: String,
+    on_event: Option<EventCallback>,
     auto_stop_thread: Option<thread::JoinHandle<()>>,
     stop_flag: Arc<AtomicBool>,
+    eos: EosReached,
 }
 
 impl GstRecorder {
     pub fn new(path: &str) -> Self {
+        Self::with_events(path, None)
+    }
+
+    pub fn with_events(path: &str, on_event: Option<EventCallback>) -> Self {
+        Self::with_options(path, RecordingOptions::default(), on_event)
+    }
+
+    pub fn with_options(
+        path: &str,
+        options: RecordingOptions,
+        on_event: Option<EventCallback>,
+    ) -> Self {
         let pipeline = gst::Pipeline::new();
 
+        let format = options
+            .format
+            .or_else(|| RecordingFormat::from_path(path))
+            .unwrap_or(RecordingFormat::Opus);
+        debug!("recording {} as {:?}", path, format);
+
         let autoaudiosrc = add_element_by_name("autoaudiosrc");
         let queue = add_element_by_name("queue");
         let audioconvert = add_element_by_name("audioconvert");
         let audioresample = add_element_by_name("audioresample");
-        let opusenc = add_element_by_name("opusenc");
-        let oggmux = add_element_by_name("oggmux");
+        let capsfilter = gst::ElementFactory::make("capsfilter")
+            .property("caps", options.config.caps())
+            .build()
+            .expect("failed to create capsfilter element");
         let filesink = add_element_by_name("filesink");
         filesink.set_property("location", path);
 
-        let elements = &[
-            &autoaudiosrc,
-            &queue,
-            &audioconvert,
-            &audioresample,
-            &opusenc,
-            &oggmux,
-            &filesink,
-        ];
-        pipeline.add_many(elements).unwrap();
-        gst::Element::link_many(elements).unwrap();
+        let mut elements = vec![autoaudiosrc, queue, audioconvert, audioresample, capsfilter];
+        elements.extend(build_encoder(format, &options));
+        elements.push(filesink);
+
+        pipeline.add_many(&elements).unwrap();
+        gst::Element::link_many(&elements).unwrap();
 
-        setup_bus_watch(&pipeline);
+        // The bus is polled by the watch thread, so the end of stream is caught as it is
+        // posted instead.
+        let eos: EosReached = Arc::new((Mutex::new(false), Condvar::new()));
+        let eos_ref = Arc::clone(&eos);
+        pipeline
+            .bus()
+            .unwrap()
+            .set_sync_handler(move |_bus, message| {
+                if let gst::MessageView::Eos(..) = message.view() {
+                    let (lock, cvar) = &*eos_ref;
+                    *lock.lock().unwrap() = true;
+                    cvar.notify_all();
+                }
+                gst::BusSyncReply::Pass
+            });
+        setup_bus_watch(&pipeline, on_event.clone());
 
         Self {
             pipeline,
+            path: path.to_string(),
+            on_event,
             auto_stop_thread: None,
             stop_flag: Arc::new(AtomicBool::new(false)),
+            eos,
         }
     }
 
@@ -55,14 +202,18 @@ impl GstRecorder {
         let pipeline_ref = self.pipeline.downgrade();
         let end_time = Instant::now() + duration;
         let stop_flag = Arc::clone(&self.stop_flag);
+        let path = self.path.clone();
+        let on_event = self.on_event.clone();
+        let eos = Arc::clone(&self.eos);
         let handle = thread::spawn(move || {
             while !stop_flag.load(Ordering::Relaxed) && Instant::now() < end_time {
                 thread::sleep(Duration::from_millis(100));
             }
             if !stop_flag.load(Ordering::Relaxed) {
                 let pipeline = pipeline_ref.upgrade().unwrap();
-                set_pipeline_state(&pipeline, gst::State::Null);
+                finalize(&pipeline, &eos);
                 debug!("recording auto stopped");
+                notify_finalized(&path, on_event.as_ref());
             }
         });
 
@@ -75,6 +226,69 @@ impl GstRecorder {
             handle.join().unwrap();
             self.auto_stop_thread = None;
         }
-        set_pipeline_state(&self.pipeline, gst::State::Null);
+        if self.pipeline.current_state() != gst::State::Null {
+            finalize(&self.pipeline, &self.eos);
+            notify_finalized(&self.path, self.on_event.as_ref());
+        }
+    }
+}
+
+/// Encoder, followed by its muxer when the format needs a container.
+pub(crate) fn build_encoder(
+    format: RecordingFormat,
+    options: &RecordingOptions,
+) -> Vec<gst::Element> {
+    match format {
+        RecordingFormat::Wav => vec![add_element_by_name("wavenc")],
+        RecordingFormat::Flac => vec![add_element_by_name("flacenc")],
+        RecordingFormat::Opus => {
+            let opusenc = add_element_by_name("opusenc");
+            if let Some(bitrate) = options.bitrate {
+                // bit/s, within the range accepted by opusenc
+                opusenc.set_property("bitrate", (bitrate.clamp(4, 650) * 1000) as i32);
+            }
+            vec![opusenc, add_element_by_name("oggmux")]
+        }
+        RecordingFormat::Mp3 => {
+            let lamemp3enc = add_element_by_name("lamemp3enc");
+            if let Some(bitrate) = options.bitrate {
+                lamemp3enc.set_property_from_str("target", "bitrate");
+                lamemp3enc.set_property("bitrate", bitrate.clamp(8, 320) as i32);
+                lamemp3enc.set_property("cbr", true);
+            } else if let Some(quality) = options.quality {
+                // lame quality goes from 0 (best) to 10
+                lamemp3enc.set_property_from_str("target", "quality");
+                lamemp3enc.set_property("quality", (1.0 - quality.clamp(0.0, 1.0)) * 10.0);
+            }
+            vec![lamemp3enc]
+        }
+    }
+}
+
+/// Ends the stream so that the encoder and the muxer complete the file, WAV and FLAC
+/// headers being rewritten with the final size, then stops the pipeline.
+fn finalize(pipeline: &gst::Pipeline, eos: &EosReached) {
+    let (lock, cvar) = &**eos;
+    *lock.lock().unwrap() = false;
+    // a pipeline that did not start has nothing to complete
+    if pipeline.current_state() == gst::State::Playing {
+        if pipeline.send_event(gst::event::Eos::new()) {
+            let (_, timeout) = cvar
+                .wait_timeout_while(lock.lock().unwrap(), EOS_TIMEOUT, |eos| !*eos)
+                .unwrap();
+            if timeout.timed_out() {
+                warn!("Recording not completed in time, the file may be truncated");
+            }
+        } else {
+            warn!("Failed to end the recording, the file may be truncated");
+        }
+    }
+    set_pipeline_state(pipeline, gst::State::Null);
+}
+
+fn notify_finalized(path: &str, on_event: Option<&EventCallback>) {
+    if let Some(on_event) = on_event {
+        let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        on_event(GstEvent::RecordingFinalized { size });
     }
 }
@@ -0,0 +1,104 @@
+use crate::gst_events::{EventCallback, GstEvent};
+use crate::gst_player::link_decoded_audio;
+use crate::gst_recorder::{build_encoder, RecordingConfig, RecordingFormat, RecordingOptions};
+use crate::gst_utils::add_element_by_name;
+use crate::gst_utils::set_pipeline_state;
+use gst::prelude::*;
+use log::debug;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Longest time without the position moving before the conversion is given up, the
+// decoding being stuck.
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Decodes `input` and encodes it to `output` in `format`, converted to `config`.
+/// Blocks until the file is written, reporting `TranscodeProgress` events meanwhile.
+pub fn transcode(
+    input: &Path,
+    output: &Path,
+    format: RecordingFormat,
+    config: RecordingConfig,
+    on_event: Option<EventCallback>,
+) -> Result<(), glib::Error> {
+    debug!("transcoding {:?} to {:?} as {:?}", input, output, format);
+    let pipeline = gst::Pipeline::new();
+
+    let filesrc = gst::ElementFactory::make("filesrc")
+        .property("location", input.to_string_lossy().as_ref())
+        .build()
+        .expect("failed to create filesrc element");
+    let decodebin = add_element_by_name("decodebin");
+    let queue = add_element_by_name("queue");
+    let audioconvert = add_element_by_name("audioconvert");
+    let audioresample = add_element_by_name("audioresample");
+    let capsfilter = gst::ElementFactory::make("capsfilter")
+        .property("caps", config.caps())
+        .build()
+        .expect("failed to create capsfilter element");
+    let filesink = gst::ElementFactory::make("filesink")
+        .property("location", output.to_string_lossy().as_ref())
+        .build()
+        .expect("failed to create filesink element");
+
+    let options = RecordingOptions {
+        format: Some(format),
+        config,
+        ..Default::default()
+    };
+    let mut elements = vec![queue.clone(), audioconvert, audioresample, capsfilter];
+    elements.extend(build_encoder(format, &options));
+    elements.push(filesink);
+
+    pipeline.add_many([&filesrc, &decodebin]).unwrap();
+    pipeline.add_many(&elements).unwrap();
+    filesrc.link(&decodebin).unwrap();
+    gst::Element::link_many(&elements).unwrap();
+    link_decoded_audio(&decodebin, &queue);
+
+    set_pipeline_state(&pipeline, gst::State::Playing);
+    let result = wait_for_eos(&pipeline, on_event.as_ref());
+    set_pipeline_state(&pipeline, gst::State::Null);
+    result
+}
+
+fn wait_for_eos(
+    pipeline: &gst::Pipeline,
+    on_event: Option<&EventCallback>,
+) -> Result<(), glib::Error> {
+    let bus = pipeline.bus().unwrap();
+    let mut last_position = None;
+    let mut last_progress = Instant::now();
+    loop {
+        let message = bus.timed_pop_filtered(
+            gst::ClockTime::from_mseconds(200),
+            &[gst::MessageType::Eos, gst::MessageType::Error],
+        );
+
+        use gst::MessageView;
+        match message.as_ref().map(|m| m.view()) {
+            Some(MessageView::Eos(..)) => return Ok(()),
+            Some(MessageView::Error(err)) => return Err(err.error()),
+            _ => {
+                let position = pipeline.query_position::<gst::ClockTime>();
+                if position != last_position {
+                    last_position = position;
+                    last_progress = Instant::now();
+                } else if last_progress.elapsed() > STALL_TIMEOUT {
+                    return Err(glib::Error::new(
+                        gst::CoreError::Failed,
+                        "Conversion not progressing, the decoding is stuck",
+                    ));
+                }
+                if let (Some(position), Some(on_event)) = (position, on_event) {
+                    on_event(GstEvent::TranscodeProgress {
+                        position: Duration::from_nanos(position.nseconds()),
+                        duration: pipeline
+                            .query_duration::<gst::ClockTime>()
+                            .map(|d| Duration::from_nanos(d.nseconds())),
+                    });
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use gst::glib;
+use crate::gst_events::{EventCallback, GstEvent};
 use gst::prelude::*;
 use log::{error, info};
+use std::sync::mpsc;
+use std::thread;
 
 pub fn add_element_by_name(name: &str) -> gst::Element {
     let element = gst::ElementFactory::make(name)
@@ -9,29 +11,70 @@ pub fn add_element_by_name(name: &str) -> gst::Element {
     element
 }
 
-pub fn setup_bus_watch(pipeline: &gst::Pipeline) {
+/// Polls the pipeline bus from a dedicated thread until the pipeline is dropped.
+/// A glib watch would require a running main loop, which the server does not have.
+pub fn setup_bus_watch(pipeline: &gst::Pipeline, on_event: Option<EventCallback>) {
     let bus = pipeline.bus().unwrap();
-    let _bus_watch = bus
-        .add_watch(move |_bus, message| {
-            use gst::MessageView;
-            match message.view() {
-                MessageView::Error(err) => {
-                    error!(
-                        "Error received from element {:?} {}",
-                        err.src().map(|s| s.path_string()),
-                        err.error()
-                    );
-                    error!("Debugging information: {:?}", err.debug());
-                    glib::ControlFlow::Break
-                }
-                MessageView::Eos(..) => {
-                    info!("Reached end of stream");
-                    glib::ControlFlow::Break
+    let pipeline_weak = pipeline.downgrade();
+
+    thread::spawn(move || loop {
+        let Some(message) = bus.timed_pop(gst::ClockTime::from_mseconds(100)) else {
+            if pipeline_weak.upgrade().is_none() {
+                break;
+            }
+            continue;
+        };
+        let Some(pipeline) = pipeline_weak.upgrade() else {
+            break;
+        };
+
+        use gst::MessageView;
+        let event = match message.view() {
+            MessageView::Error(err) => {
+                let element = err.src().map(|s| s.path_string().to_string());
+                error!("Error received from element {:?} {}", element, err.error());
+                error!("Debugging information: {:?}", err.debug());
+                Some(GstEvent::Error {
+                    element,
+                    message: err.error().to_string(),
+                })
+            }
+            MessageView::Eos(..) => {
+                info!("Reached end of stream");
+                Some(GstEvent::Eos)
+            }
+            MessageView::SegmentDone(..) => Some(GstEvent::SegmentDone),
+            MessageView::StateChanged(s)
+                if message.src() == Some(pipeline.upcast_ref::<gst::Object>()) =>
+            {
+                match (s.old(), s.current(), s.pending()) {
+                    (_, gst::State::Playing, _) => Some(GstEvent::Started),
+                    // a pending state means the pipeline is going down to NULL
+                    (gst::State::Playing, gst::State::Paused, gst::State::VoidPending) => {
+                        Some(GstEvent::Paused)
+                    }
+                    _ => None,
                 }
-                _ => glib::ControlFlow::Continue,
             }
-        })
-        .unwrap();
+            _ => None,
+        };
+
+        if let (Some(event), Some(on_event)) = (event, on_event.as_ref()) {
+            on_event(event);
+        }
+    });
+}
+
+/// Returns a sender whose events are passed to `on_event` from a dedicated thread.
+/// Used to leave the streaming threads before acting on the pipeline.
+pub fn spawn_event_thread(on_event: EventCallback) -> mpsc::Sender<GstEvent> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for event in rx {
+            on_event(event);
+        }
+    });
+    tx
 }
 
 pub fn set_pipeline_state(pipeline: &gst::Pipeline, state: gst::State) {
@@ -1,3 +1,9 @@
+pub mod gst_discoverer;
+pub mod gst_events;
+pub mod gst_loudness;
+pub mod gst_microphone;
+pub mod gst_mixer;
 pub mod gst_player;
 pub mod gst_recorder;
+pub mod gst_transcoder;
 mod gst_utils;
@@ -0,0 +1,71 @@
+use crate::store::JsonStore;
+use gst_wrapper::gst_loudness::measure_loudness;
+use std::path::{Path, PathBuf};
+
+/// Largest correction applied to a quiet sound, in dB, so that noise is not boosted.
+const MAX_BOOST_DB: f64 = 12.0;
+
+/// Measured loudness of the sound files, kept in a file so that the files are only
+/// analysed once. Playback applies the gain bringing them to the target loudness.
+pub struct LoudnessStore {
+    // LUFS, None when the sounds are played as they are
+    target: Option<f64>,
+    // integrated loudness in LUFS by name of the sound file
+    measures: JsonStore<f64>,
+}
+
+impl LoudnessStore {
+    /// Reads the measures stored in `file`, starting empty if there are none.
+    pub fn load(file: PathBuf, target: Option<f64>) -> Self {
+        Self {
+            target,
+            measures: JsonStore::load(file),
+        }
+    }
+
+    /// Integrated loudness of the file in LUFS, None if it was not measured.
+    pub fn loudness(&self, name: &str) -> Option<f64> {
+        self.measures.get(name)
+    }
+
+    /// True if the sounds are brought to a target loudness, the uploads being analysed then.
+    pub fn normalizes(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Correction of the file in dB, 0 if it was not measured or if no target is set.
+    pub fn gain_db(&self, name: &str) -> f64 {
+        match (self.target, self.loudness(name)) {
+            (Some(target), Some(loudness)) => (target - loudness).min(MAX_BOOST_DB),
+            _ => 0.0,
+        }
+    }
+
+    /// Linear gain applied when the file is played.
+    pub fn gain(&self, name: &str) -> f64 {
+        10f64.powf(self.gain_db(name) / 20.0)
+    }
+
+    /// Measures the file at `path`, blocking until it is decoded, and stores the result.
+    pub fn analyse(&self, name: &str, path: &Path) -> Result<Option<f64>, glib::Error> {
+        let loudness = measure_loudness(path)?;
+        self.measures.set(name, loudness);
+        Ok(loudness)
+    }
+
+    pub fn remove(&self, name: &str) {
+        self.measures.remove(name);
+    }
+
+    pub fn rename(&self, from: &str, to: &str) {
+        self.measures.rename(from, to);
+    }
+
+    pub fn copy(&self, from: &str, to: &str) {
+        self.measures.copy(from, to);
+    }
+
+    pub fn remove_dir(&self, dir: &str) {
+        self.measures.remove_dir(dir);
+    }
+}
@@ -1,24 +1,55 @@
 use log::{debug, info, warn};
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::time::Duration;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use std::{env, fs};
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
 
-use gst_wrapper::gst_player::GstPlayer;
-use gst_wrapper::gst_recorder::GstRecorder;
+use gst_wrapper::gst_discoverer::{discover, AudioInfo};
+use gst_wrapper::gst_events::{EventCallback, GstEvent};
+use gst_wrapper::gst_microphone::{AudioChunk, ChunkCallback, GstMicrophone, StreamEncoding};
+use gst_wrapper::gst_mixer::GstMixer;
+use gst_wrapper::gst_player::{Fades, GstPlayer, StreamSource};
+use gst_wrapper::gst_recorder::{
+    GstRecorder, RecordingConfig, RecordingFormat, RecordingOptions, SampleFormat,
+};
+use gst_wrapper::gst_transcoder::transcode;
 
 use tonic::{transport::Server, Request, Response, Status};
 
 use clap::Parser;
+use loudness::LoudnessStore;
 use reachy_api::component::audio::audio_service_server::{AudioService, AudioServiceServer};
+use reachy_api::component::audio::ext::audio_ext_service_server::{
+    AudioExtService, AudioExtServiceServer,
+};
+use reachy_api::component::audio::ext::{
+    self, audio_event, play_stream_request, upload_request, AudioEvent, AudioFileInfo, AudioFormat,
+    AudioTags, Directory, DownloadChunk, DownloadRequest, EndOfStream, FileLoudness, FileTransfer,
+    ListRequest, LoudnessReport, MicrophoneChunk, MicrophoneRequest, OverwritePolicy, Paused,
+    PipelineError, PlayRequest, PlayStreamRequest, Playback, PlaybackHandle, PlaybackPosition,
+    PlaybackQueue, PlaybackTarget, PlaybackVolume, Playbacks, RecordRequest, RecordingFinalized,
+    RemoveDirectoryRequest, SeekRequest, SegmentDone, SoundFile, SoundFiles, Started,
+    TranscodeProgress, UploadOffset, UploadRequest, UploadResult, Volume,
+};
 use reachy_api::component::audio::{
     audio_file_request, AudioAck, AudioFile, AudioFileRequest, AudioFiles,
 };
 use reachy_api::error::Error;
+use sounds::{PartialFile, SoundLibrary};
+use tags::{TagStore, Tags};
+
+mod loudness;
+mod sounds;
+mod store;
+mod tags;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -30,99 +61,837 @@ struct Args {
     /// grpc server port
     #[arg(long, default_value_t = 50063)]
     grpc_port: u16,
+
+    /// directory of the uploaded and recorded sounds, a temporary directory if unset
+    #[arg(long, env = "REACHY_SOUNDS_DIR")]
+    sounds_dir: Option<PathBuf>,
+
+    /// read-only directory of sounds listed along with the uploaded ones
+    #[arg(long, env = "REACHY_SYSTEM_SOUNDS_DIR")]
+    system_sounds_dir: Option<PathBuf>,
+
+    /// fade in when a sound starts, in milliseconds
+    #[arg(long, default_value_t = 20)]
+    fade_in_ms: u64,
+
+    /// fade out when a sound is stopped, in milliseconds
+    #[arg(long, default_value_t = 100)]
+    fade_out_ms: u64,
+
+    /// overlap between two files of the queue, in milliseconds. 0 plays them one after the other
+    #[arg(long, default_value_t = 0)]
+    crossfade_ms: u64,
+
+    /// largest file accepted by an upload, in MiB
+    #[arg(long, default_value_t = 100)]
+    max_upload_mb: u64,
+
+    /// format uploaded files are converted to (wav, flac, opus or mp3), kept as uploaded if unset
+    #[arg(long, value_parser = parse_recording_format)]
+    transcode_format: Option<RecordingFormat>,
+
+    /// sample rate of the converted files
+    #[arg(long, default_value_t = 48000)]
+    transcode_rate: u32,
+
+    /// number of channels of the converted files
+    #[arg(long, default_value_t = 2)]
+    transcode_channels: u32,
+
+    /// loudness the sounds are brought to when played, in LUFS (-23 for EBU R128).
+    /// The uploads are only analysed when it is set
+    #[arg(long, allow_negative_numbers = true)]
+    target_loudness: Option<f64>,
 }
 
 enum GstStatus {
-    Play,
-    Record,
+    // playback id, number of plays (None to loop forever) and fades
+    Play(u64, Option<u32>, Fades),
+    // playback id, format of raw samples (None for encoded data) and first chunk,
+    // replies the source fed with the next chunks, or why the stream could not start
+    PlayStream(
+        u64,
+        Option<RecordingConfig>,
+        Vec<u8>,
+        oneshot::Sender<Result<StreamSource, String>>,
+    ),
+    // recording options and time
+    Record(RecordingOptions, Duration),
     StopPlaying,
+    // commands on a playback reply whether it was found,
+    // the last started playback being the target when no id is given
+    Stop(u64, Ack),
     StopRecording,
+    Pause(Option<u64>, Ack),
+    Resume(Option<u64>, Ack),
+    Seek(Option<u64>, Duration, Ack),
+    Position(Option<u64>, oneshot::Sender<Option<PositionReply>>),
+    SetVolume,
+    SetPlaybackVolume(u64, Ack),
+    Mute(bool),
+    Volume(oneshot::Sender<(f32, bool)>),
+    Playbacks(oneshot::Sender<Vec<(u64, String, bool)>>),
+    Tick,
+    Eos(u64),
+    Enqueue(u64),
+    Skip,
+    ClearQueue,
+    Queue(oneshot::Sender<(Option<QueueEntry>, Vec<QueueEntry>)>),
 }
 
+type Ack = oneshot::Sender<bool>;
+type PositionReply = (Option<Duration>, Option<Duration>);
+// playback id and path of a queued file
+type QueueEntry = (u64, String);
+// format and raw audio uploaded files are converted to
+type Conversion = (RecordingFormat, RecordingConfig);
+
+type GstMessage = (GstStatus, Option<String>, Option<f32>);
+// playback id, path of the file and event
+type EventMessage = (Option<u64>, String, GstEvent);
+
 pub struct SDKAudioService {
-    sounds_path: PathBuf,
-    tx: mpsc::Sender<(GstStatus, Option<String>, Option<f32>)>,
+    library: SoundLibrary,
+    tx: mpsc::Sender<GstMessage>,
+    events: broadcast::Sender<EventMessage>,
+    next_id: AtomicU64,
+    // applied when a play request does not set its own
+    fades: Fades,
+    // discovery results with the modification time of the file they were made at
+    audio_infos: Mutex<HashMap<PathBuf, (SystemTime, Result<AudioInfo, glib::Error>)>>,
+    // same for the checksums of the files
+    checksums: Mutex<HashMap<PathBuf, (SystemTime, String)>>,
+    // in bytes
+    max_upload_size: u64,
+    // applied to the uploads that do not set their own
+    conversion: Option<Conversion>,
+    loudness: Arc<LoudnessStore>,
+    tags: TagStore,
 }
 
 impl SDKAudioService {
-    pub async fn new() -> Self {
-        let mut sounds_path = env::temp_dir();
-        sounds_path.push("Reachy_SDK_audio_server");
-        std::fs::create_dir_all(&sounds_path).unwrap();
+    pub async fn new(
+        library: SoundLibrary,
+        fades: Fades,
+        crossfade: Duration,
+        max_upload_size: u64,
+        conversion: Option<Conversion>,
+        loudness: LoudnessStore,
+        tags: TagStore,
+    ) -> Self {
+        let loudness = Arc::new(loudness);
+        let (events, _) = broadcast::channel(64);
+        let tx = SDKAudioService::spawn_sync_thread(
+            library.clone(),
+            events.clone(),
+            fades,
+            crossfade,
+            Arc::clone(&loudness),
+        )
+        .await;
 
-        let tx = SDKAudioService::spawn_sync_thread().await;
-
-        Self { sounds_path, tx }
+        Self {
+            library,
+            tx,
+            events,
+            next_id: AtomicU64::new(1),
+            fades,
+            audio_infos: Mutex::new(HashMap::new()),
+            checksums: Mutex::new(HashMap::new()),
+            max_upload_size,
+            conversion,
+            loudness,
+            tags,
+        }
     }
 
-    async fn spawn_sync_thread() -> mpsc::Sender<(GstStatus, Option<String>, Option<f32>)> {
-        let mut player: Option<GstPlayer> = None;
+    async fn spawn_sync_thread(
+        library: SoundLibrary,
+        events: broadcast::Sender<EventMessage>,
+        fades: Fades,
+        crossfade: Duration,
+        loudness: Arc<LoudnessStore>,
+    ) -> mpsc::Sender<GstMessage> {
+        let mixer = GstMixer::with_events(Some(event_callback(&events, None, "")));
+        // active playbacks by id, with the path of their file
+        let mut players: HashMap<u64, (String, GstPlayer)> = HashMap::new();
+        // last started playback, target of the commands without id
+        let mut current: Option<u64> = None;
         let mut recorder: Option<GstRecorder> = None;
-        let (tx, mut rx) = mpsc::channel::<(GstStatus, Option<String>, Option<f32>)>(2);
+        // files waiting to be played once the queue playback reaches its end
+        let mut queue: VecDeque<QueueEntry> = VecDeque::new();
+        let mut queue_player: Option<u64> = None;
+        // files of the queue fade into each other when a crossfade is set
+        let queue_fades = if crossfade.is_zero() {
+            fades
+        } else {
+            Fades {
+                fade_in: crossfade,
+                fade_out: crossfade,
+            }
+        };
+        // stopped players, dropped once their fade out is over
+        let mut fading: Vec<(Instant, GstPlayer)> = Vec::new();
+        let (tx, mut rx) = mpsc::channel::<GstMessage>(2);
+
+        let sync_tx = tx.clone();
+        let tick_tx = tx.clone();
         tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
-                let (status, path, duration) = message;
+            let mut interval = tokio::time::interval(Duration::from_millis(500));
+            loop {
+                interval.tick().await;
+                if tick_tx.send((GstStatus::Tick, None, None)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // starting, seeking and stopping players wait on the pipelines, which would hold
+        // up a worker of the runtime
+        thread::spawn(move || {
+            while let Some(message) = rx.blocking_recv() {
+                let (status, path, value) = message;
                 match status {
-                    GstStatus::Play => {
+                    GstStatus::Play(id, loops, fades) => {
                         if let Some(path) = path {
-                            let mut gst_player = GstPlayer::new(path.as_str());
-                            gst_player.play();
-                            player = Some(gst_player);
+                            let gain = loudness.gain(&library.relative(&path));
+                            let on_event = playback_callback(&events, id, &path, &sync_tx);
+                            // a player that failed to start never ends, so it is not kept
+                            if let Some(player) =
+                                start_player(&mixer, &path, loops, fades, gain, on_event)
+                            {
+                                players.insert(id, (path, player));
+                                current = Some(id);
+                            }
                         } else {
                             warn!("No path provided to play audio file");
                         }
                     }
-                    GstStatus::Record => {
+                    GstStatus::PlayStream(id, pcm, first_chunk, reply) => {
+                        let name = path.unwrap_or_default();
+                        let on_event = playback_callback(&events, id, &name, &sync_tx);
+                        let (mut player, source) =
+                            GstPlayer::with_stream(&mixer, pcm, Some(on_event));
+                        player.set_fades(fades);
+                        if player.play_stream(&source, &first_chunk) {
+                            players.insert(id, (name, player));
+                            current = Some(id);
+                            let _ = reply.send(Ok(source));
+                        } else {
+                            let _ = reply.send(Err("Failed to play the stream".to_string()));
+                        }
+                    }
+                    GstStatus::Record(options, duration) => {
                         if let Some(path) = path {
-                            let mut gst_recorder = GstRecorder::new(path.as_str());
-                            if let Some(duration) = duration {
-                                gst_recorder.record(Duration::from_secs_f32(duration));
-                            } else {
-                                gst_recorder.record(Duration::from_secs_f32(60f32));
-                                warn!("Recording time unset. Recording one minute.");
-                            }
+                            let mut gst_recorder = GstRecorder::with_options(
+                                path.as_str(),
+                                options,
+                                Some(event_callback(&events, None, &path)),
+                            );
+                            gst_recorder.record(duration);
                             recorder = Some(gst_recorder);
                         } else {
                             warn!("No path provided to record audio file");
                         }
                     }
                     GstStatus::StopPlaying => {
-                        if let Some(p) = player.as_mut() {
-                            p.stop();
+                        for (_, (_, p)) in players.drain() {
+                            fade_out(p, &mut fading);
                         }
+                        queue.clear();
+                        current = None;
+                        queue_player = None;
+                    }
+                    GstStatus::Stop(id, ack) => {
+                        let player = players.remove(&id);
+                        let found = player.is_some();
+                        if let Some((_, p)) = player {
+                            fade_out(p, &mut fading);
+                        }
+                        if current == Some(id) {
+                            current = None;
+                        }
+                        if queue_player == Some(id) {
+                            queue_player = None;
+                        }
+                        let _ = ack.send(found);
                     }
                     GstStatus::StopRecording => {
                         if let Some(r) = recorder.as_mut() {
                             r.stop();
                         }
                     }
+                    GstStatus::Pause(id, ack) => {
+                        let player = id.or(current).and_then(|id| players.get_mut(&id));
+                        let found = player.is_some();
+                        if let Some((_, p)) = player {
+                            p.pause();
+                        }
+                        let _ = ack.send(found);
+                    }
+                    GstStatus::Resume(id, ack) => {
+                        let player = id.or(current).and_then(|id| players.get_mut(&id));
+                        let found = player.is_some();
+                        if let Some((_, p)) = player {
+                            p.resume();
+                        }
+                        let _ = ack.send(found);
+                    }
+                    GstStatus::Seek(id, position, ack) => {
+                        let player = id.or(current).and_then(|id| players.get_mut(&id));
+                        let found = player.is_some();
+                        if let Some((_, p)) = player {
+                            p.seek(position);
+                        }
+                        let _ = ack.send(found);
+                    }
+                    GstStatus::Position(id, reply) => {
+                        let position = id
+                            .or(current)
+                            .and_then(|id| players.get(&id))
+                            .map(|(_, p)| (p.position(), p.duration()));
+                        let _ = reply.send(position);
+                    }
+                    GstStatus::SetVolume => {
+                        if let Some(v) = value {
+                            mixer.set_volume(v as f64);
+                        }
+                    }
+                    GstStatus::SetPlaybackVolume(id, ack) => {
+                        let player = players.get_mut(&id);
+                        let found = player.is_some();
+                        if let (Some((_, p)), Some(v)) = (player, value) {
+                            p.set_volume(v as f64);
+                        }
+                        let _ = ack.send(found);
+                    }
+                    GstStatus::Mute(mute) => {
+                        mixer.mute(mute);
+                    }
+                    GstStatus::Volume(reply) => {
+                        let _ = reply.send((mixer.get_volume() as f32, mixer.is_muted()));
+                    }
+                    GstStatus::Playbacks(reply) => {
+                        let mut playbacks: Vec<_> = players
+                            .iter()
+                            .map(|(id, (path, p))| (*id, path.clone(), p.is_playing()))
+                            .collect();
+                        playbacks.sort_by_key(|(id, _, _)| *id);
+                        let _ = reply.send(playbacks);
+                    }
+                    GstStatus::Tick => {
+                        let now = Instant::now();
+                        // players are stopped when dropped
+                        fading.retain(|(end, _)| *end > now);
+
+                        // the next file of the queue starts while the current one fades out
+                        let crossfading = !crossfade.is_zero()
+                            && !queue.is_empty()
+                            && queue_player.and_then(|id| players.get(&id)).is_some_and(
+                                |(_, p)| match (p.position(), p.duration()) {
+                                    (Some(position), Some(duration)) => {
+                                        position + crossfade >= duration
+                                    }
+                                    _ => false,
+                                },
+                            );
+                        if crossfading {
+                            if let Some((_, p)) =
+                                queue_player.take().and_then(|id| players.remove(&id))
+                            {
+                                fade_out(p, &mut fading);
+                            }
+                        }
+
+                        // the queue still has to move on when nobody watches the positions
+                        if events.receiver_count() > 0 {
+                            for (id, (path, p)) in players.iter() {
+                                if !p.is_playing() {
+                                    continue;
+                                }
+                                if let Some(position) = p.position() {
+                                    let _ = events.send((
+                                        Some(*id),
+                                        path.clone(),
+                                        GstEvent::Position {
+                                            position,
+                                            duration: p.duration(),
+                                        },
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                    GstStatus::Eos(id) => {
+                        players.remove(&id);
+                        if current == Some(id) {
+                            current = None;
+                        }
+                        if queue_player == Some(id) {
+                            queue_player = None;
+                        }
+                    }
+                    GstStatus::Enqueue(id) => {
+                        if let Some(path) = path {
+                            queue.push_back((id, path));
+                        } else {
+                            warn!("No path provided to enqueue audio file");
+                        }
+                    }
+                    GstStatus::Skip => {
+                        if let Some((_, p)) = queue_player.take().and_then(|id| players.remove(&id))
+                        {
+                            fade_out(p, &mut fading);
+                        }
+                    }
+                    GstStatus::ClearQueue => {
+                        queue.clear();
+                    }
+                    GstStatus::Queue(reply) => {
+                        let playing = queue_player
+                            .and_then(|id| players.get(&id).map(|(path, _)| (id, path.clone())));
+                        let _ = reply.send((playing, queue.iter().cloned().collect()));
+                    }
+                }
+
+                // the queue plays its files one after the other, mixed with the other sounds
+                // the files that cannot be played are skipped
+                while queue_player.is_none() {
+                    let Some((id, path)) = queue.pop_front() else {
+                        break;
+                    };
+                    let on_event = playback_callback(&events, id, &path, &sync_tx);
+                    if let Some(player) = start_player(
+                        &mixer,
+                        &path,
+                        Some(1),
+                        queue_fades,
+                        loudness.gain(&library.relative(&path)),
+                        on_event,
+                    ) {
+                        players.insert(id, (path, player));
+                        queue_player = Some(id);
+                    }
                 }
             }
         });
         tx
     }
 
+    /// Sends a command to a playback, failing with not_found if it is not active.
+    async fn send_playback_command(
+        &self,
+        status: impl FnOnce(Ack) -> GstStatus,
+        value: Option<f32>,
+    ) -> Result<(), Status> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        let _ = self.tx.send((status(ack_tx), None, value)).await;
+        match ack_rx.await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Status::not_found("Playback not found")),
+            Err(e) => Err(Status::internal(format!("Failed to reach playback: {}", e))),
+        }
+    }
+
+    fn to_playback(&self, id: u64, path: &str, playing: bool) -> Playback {
+        Playback {
+            id,
+            file: Some(AudioFile {
+                path: self.library.relative(path),
+                duration: None,
+            }),
+            playing,
+        }
+    }
+
     pub fn list_audio_files(&self) -> Vec<AudioFile> {
-        let mut files = Vec::new();
-
-        if let Ok(entries) = fs::read_dir(&self.sounds_path) {
-            for entry in entries {
-                let path = entry.unwrap().path();
-                if let Some(extension) = path.extension() {
-                    if extension == "mp3" || extension == "wav" || extension == "ogg" {
-                        if let Some(file_name) = path.file_name() {
-                            if let Some(file_name_str) = file_name.to_str() {
-                                files.push(AudioFile {
-                                    path: file_name_str.to_string(),
-                                    duration: None,
-                                });
-                            }
-                        }
-                    }
+        self.library
+            .list()
+            .into_iter()
+            .map(|(name, path)| self.to_audio_file(name, &path))
+            .collect()
+    }
+
+    fn list_sound_files(&self, request: &ListRequest) -> Vec<SoundFile> {
+        self.library
+            .list()
+            .into_iter()
+            .filter(|(name, _)| {
+                name.starts_with(&request.prefix) && self.tags.matches(name, &request.tags)
+            })
+            .map(|(name, path)| SoundFile {
+                sha256: self.checksum(&path).unwrap_or_default(),
+                size: fs::metadata(&path).map_or(0, |m| m.len()),
+                tags: self
+                    .tags
+                    .get(&name)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+                file: Some(self.to_audio_file(name, &path)),
+            })
+            .collect()
+    }
+
+    fn to_audio_file(&self, name: String, path: &Path) -> AudioFile {
+        AudioFile {
+            path: name,
+            duration: self
+                .audio_info(path)
+                .ok()
+                .and_then(|info| info.duration)
+                .map(|d| d.as_secs_f32()),
+        }
+    }
+
+    /// Moves a complete upload in place, once checked that it matches the expected
+    /// checksum and can be decoded. The file is converted first if `conversion` is set.
+    fn finish_upload(
+        &self,
+        upload: PartialFile,
+        sha256: Option<&str>,
+        conversion: Option<Conversion>,
+    ) -> Result<UploadResult, Status> {
+        let failed = |details| UploadResult {
+            ack: Some(failed_ack(details)),
+            file: None,
+        };
+
+        if let Some(expected) = sha256 {
+            let actual = tokio::task::block_in_place(|| sounds::sha256(upload.temp_path()))
+                .map_err(|e| Status::internal(format!("Failed to read file: {}", e)))?;
+            if !actual.eq_ignore_ascii_case(expected) {
+                upload.discard();
+                return Ok(failed(format!(
+                    "Checksum mismatch, expected {} but received {}",
+                    expected, actual
+                )));
+            }
+        }
+
+        if let Err(e) = tokio::task::block_in_place(|| discover(upload.temp_path())) {
+            upload.discard();
+            return Ok(failed(format!("File cannot be decoded: {}", e)));
+        }
+
+        let path = match conversion {
+            None => {
+                let path = upload.path().to_path_buf();
+                upload
+                    .commit()
+                    .map_err(|e| Status::internal(format!("Failed to save file: {}", e)))?;
+                path
+            }
+            Some((format, config)) => {
+                let path = upload.path().with_extension(format.extension());
+                // another file, with its own tags and loudness, is not silently replaced
+                if path != upload.path() && path.exists() {
+                    upload.discard();
+                    return Ok(failed(format!(
+                        "Converted file {} already exists",
+                        self.library.relative(&path.to_string_lossy())
+                    )));
+                }
+                let converted = PartialFile::create_converted(path.clone())
+                    .map_err(|e| Status::internal(format!("Failed to create file: {}", e)))?;
+                let on_event = event_callback(&self.events, None, &path.to_string_lossy());
+                let result = tokio::task::block_in_place(|| {
+                    transcode(
+                        upload.temp_path(),
+                        converted.temp_path(),
+                        format,
+                        config,
+                        Some(on_event),
+                    )
+                });
+                upload.discard();
+                if let Err(e) = result {
+                    return Ok(failed(format!("Failed to transcode file: {}", e)));
                 }
+                converted
+                    .commit()
+                    .map_err(|e| Status::internal(format!("Failed to save file: {}", e)))?;
+                path
+            }
+        };
+
+        // the measure of a replaced file does not hold for the new one
+        let name = self.library.relative(&path.to_string_lossy());
+        self.loudness.remove(&name);
+        if self.loudness.normalizes() {
+            if let Err(e) = tokio::task::block_in_place(|| self.loudness.analyse(&name, &path)) {
+                warn!(
+                    "Failed to measure the loudness of {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+
+        Ok(UploadResult {
+            ack: Some(AudioAck {
+                success: Some(true),
+                error: None,
+            }),
+            file: Some(tokio::task::block_in_place(|| {
+                self.to_audio_file(name, &path)
+            })),
+        })
+    }
+
+    fn too_large_ack(&self) -> AudioAck {
+        failed_ack(format!(
+            "File exceeds the maximum upload size of {} bytes",
+            self.max_upload_size
+        ))
+    }
+
+    fn too_large_result(&self) -> UploadResult {
+        UploadResult {
+            ack: Some(self.too_large_ack()),
+            file: None,
+        }
+    }
+
+    /// Hex encoded SHA-256 of the file, computed again only when the file is modified.
+    fn checksum(&self, path: &Path) -> std::io::Result<String> {
+        let modified = fs::metadata(path).and_then(|m| m.modified())?;
+        if let Some((hashed_at, sha256)) = self.checksums.lock().unwrap().get(path) {
+            if *hashed_at == modified {
+                return Ok(sha256.clone());
+            }
+        }
+
+        let sha256 = sounds::sha256(path)?;
+        self.checksums
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (modified, sha256.clone()));
+        Ok(sha256)
+    }
+
+    /// Fails with not_found if the file does not exist, and with failed_precondition if it
+    /// cannot be decoded, so that the player is not started for nothing.
+    fn check_playable(&self, path: &Path) -> Result<(), Status> {
+        if !path.is_file() {
+            return Err(Status::not_found("File not found"));
+        }
+        tokio::task::block_in_place(|| self.audio_info(path))
+            .map_err(|e| Status::failed_precondition(format!("File cannot be decoded: {}", e)))?;
+        Ok(())
+    }
+
+    /// Probes the file, the last result being reused while the file is not modified.
+    fn audio_info(&self, path: &Path) -> Result<AudioInfo, glib::Error> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let (Some(modified), Some((probed_at, info))) =
+            (modified, self.audio_infos.lock().unwrap().get(path))
+        {
+            if *probed_at == modified {
+                return info.clone();
+            }
+        }
+
+        // not locked meanwhile, so that a slow file does not hold up the other ones
+        let info = discover(path);
+        if let Some(modified) = modified {
+            self.audio_infos
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), (modified, info.clone()));
+        }
+        info
+    }
+}
+
+/// None if the player could not start, its error being reported through `on_event`.
+fn start_player(
+    mixer: &GstMixer,
+    path: &str,
+    loops: Option<u32>,
+    fades: Fades,
+    gain: f64,
+    on_event: EventCallback,
+) -> Option<GstPlayer> {
+    let mut gst_player = GstPlayer::with_mixer(mixer, path, Some(on_event));
+    gst_player.set_loops(loops);
+    gst_player.set_fades(fades);
+    gst_player.set_gain(gain);
+    gst_player.play().then_some(gst_player)
+}
+
+fn playback_callback(
+    events: &broadcast::Sender<EventMessage>,
+    id: u64,
+    path: &str,
+    sync_tx: &mpsc::Sender<GstMessage>,
+) -> EventCallback {
+    let forward = event_callback(events, Some(id), path);
+    let sync_tx = sync_tx.clone();
+    // the end of stream is reported to the sync thread so that it can release the player
+    Arc::new(move |event| {
+        if let GstEvent::Eos = event {
+            let _ = sync_tx.blocking_send((GstStatus::Eos(id), None, None));
+        }
+        forward(event);
+    })
+}
+
+/// Fades the player out, keeping it in `fading` until it is silent.
+fn fade_out(mut player: GstPlayer, fading: &mut Vec<(Instant, GstPlayer)>) {
+    let duration = player.fade_out();
+    if !duration.is_zero() {
+        fading.push((Instant::now() + duration, player));
+    }
+}
+
+fn event_callback(
+    events: &broadcast::Sender<EventMessage>,
+    id: Option<u64>,
+    path: &str,
+) -> EventCallback {
+    let events = events.clone();
+    let path = path.to_string();
+    Arc::new(move |event| {
+        let _ = events.send((id, path.clone(), event));
+    })
+}
+
+/// Converts seconds from a request, `default` being used when unset. Negative, infinite
+/// and NaN values are rejected, `what` naming the value in the error.
+fn to_duration(seconds: Option<f32>, default: Duration, what: &str) -> Result<Duration, Status> {
+    match seconds {
+        None => Ok(default),
+        Some(s) => Duration::try_from_secs_f32(s).map_err(|_| {
+            Status::invalid_argument(format!("{} must be a non-negative number of seconds", what))
+        }),
+    }
+}
+
+/// Reply to a request that could not be carried out, `details` telling why.
+fn failed_ack(details: String) -> AudioAck {
+    AudioAck {
+        success: Some(false),
+        error: Some(Error { details }),
+    }
+}
+
+/// Reply to a rename or copy whose destination exists, None if it has to be replaced.
+fn existing_destination_ack(overwrite: OverwritePolicy) -> Option<AudioAck> {
+    match overwrite {
+        OverwritePolicy::Fail => Some(failed_ack("Destination file already exists".to_string())),
+        OverwritePolicy::Replace => None,
+        OverwritePolicy::Skip => Some(AudioAck {
+            success: Some(true),
+            error: None,
+        }),
+    }
+}
+
+fn to_recording_format(format: AudioFormat) -> Option<RecordingFormat> {
+    match format {
+        AudioFormat::Unspecified => None,
+        AudioFormat::Wav => Some(RecordingFormat::Wav),
+        AudioFormat::Flac => Some(RecordingFormat::Flac),
+        AudioFormat::Opus => Some(RecordingFormat::Opus),
+        AudioFormat::Mp3 => Some(RecordingFormat::Mp3),
+    }
+}
+
+fn parse_recording_format(format: &str) -> Result<RecordingFormat, String> {
+    RecordingFormat::from_extension(format).ok_or_else(|| format!("unknown format {}", format))
+}
+
+fn to_recording_config(config: ext::RecordingConfig) -> Result<RecordingConfig, Status> {
+    if config.rate.is_some_and(|r| !(8000..=192000).contains(&r)) {
+        return Err(Status::invalid_argument(
+            "Sample rate must be between 8000 and 192000",
+        ));
+    }
+    if config.channels.is_some_and(|c| !(1..=8).contains(&c)) {
+        return Err(Status::invalid_argument(
+            "Channel count must be between 1 and 8",
+        ));
+    }
+
+    Ok(RecordingConfig {
+        rate: config.rate,
+        channels: config.channels,
+        sample_format: match config.sample_format() {
+            ext::SampleFormat::Unspecified => None,
+            ext::SampleFormat::S16 => Some(SampleFormat::S16),
+            ext::SampleFormat::S24 => Some(SampleFormat::S24),
+            ext::SampleFormat::S32 => Some(SampleFormat::S32),
+            ext::SampleFormat::F32 => Some(SampleFormat::F32),
+        },
+    })
+}
+
+/// Recording time of a request, one minute when unset.
+fn to_recording_time(seconds: Option<f32>) -> Result<Duration, Status> {
+    if seconds.is_none() {
+        warn!("Recording time unset. Recording one minute.");
+    }
+    to_duration(seconds, Duration::from_secs(60), "Recording time")
+}
+
+fn to_microphone_chunk(chunk: AudioChunk) -> MicrophoneChunk {
+    let config = chunk.config;
+    MicrophoneChunk {
+        data: chunk.data,
+        // Opus has no raw format to describe
+        config: config.rate.map(|_| ext::RecordingConfig {
+            rate: config.rate,
+            channels: config.channels,
+            sample_format: match config.sample_format {
+                None => ext::SampleFormat::Unspecified,
+                Some(SampleFormat::S16) => ext::SampleFormat::S16,
+                Some(SampleFormat::S24) => ext::SampleFormat::S24,
+                Some(SampleFormat::S32) => ext::SampleFormat::S32,
+                Some(SampleFormat::F32) => ext::SampleFormat::F32,
             }
+            .into(),
+        }),
+    }
+}
+
+fn to_audio_event(
+    library: &SoundLibrary,
+    id: Option<u64>,
+    path: &str,
+    event: GstEvent,
+) -> AudioEvent {
+    let path = library.relative(path);
+
+    let event = match event {
+        GstEvent::Started => audio_event::Event::Started(Started {}),
+        GstEvent::Paused => audio_event::Event::Paused(Paused {}),
+        GstEvent::Position { position, duration } => {
+            audio_event::Event::Position(PlaybackPosition {
+                position: Some(position.as_secs_f32()),
+                duration: duration.map(|d| d.as_secs_f32()),
+            })
         }
-        files
+        GstEvent::Eos => audio_event::Event::EndOfStream(EndOfStream {}),
+        GstEvent::SegmentDone => audio_event::Event::SegmentDone(SegmentDone {}),
+        GstEvent::Error { element, message } => {
+            audio_event::Event::Error(PipelineError { element, message })
+        }
+        GstEvent::RecordingFinalized { size } => {
+            audio_event::Event::RecordingFinalized(RecordingFinalized { size })
+        }
+        GstEvent::TranscodeProgress { position, duration } => {
+            audio_event::Event::TranscodeProgress(TranscodeProgress {
+                position: position.as_secs_f32(),
+                duration: duration.map(|d| d.as_secs_f32()),
+            })
+        }
+    };
+
+    AudioEvent {
+        path,
+        event: Some(event),
+        id,
     }
 }
 
@@ -134,7 +903,7 @@ impl AudioService for SDKAudioService {
             request.remote_addr()
         );
 
-        let files = self.list_audio_files();
+        let files = tokio::task::block_in_place(|| self.list_audio_files());
 
         let reply = AudioFiles { files };
         Ok(Response::new(reply))
@@ -150,22 +919,25 @@ impl AudioService for SDKAudioService {
         );
 
         let mut stream = request.into_inner();
-        let mut file: Option<File> = None;
+        // the partial file is removed if the upload does not complete
+        let mut upload: Option<PartialFile> = None;
 
         while let Some(audiofile_request) = stream.next().await {
             match audiofile_request {
                 Ok(audiofile_request) => match audiofile_request.data {
                     Some(audio_file_request::Data::Info(info)) => {
-                        let mut path = self.sounds_path.clone();
-                        path.push(info.path);
+                        let path = self.library.writable(&info.path)?;
 
-                        file = Some(File::create(path).map_err(|e| {
+                        upload = Some(PartialFile::create(path).map_err(|e| {
                             Status::internal(format!("Failed to create file: {}", e))
                         })?);
                     }
                     Some(audio_file_request::Data::ChunkData(chunk_data)) => {
-                        if let Some(file) = file.as_mut() {
-                            file.write_all(&chunk_data).map_err(|e| {
+                        if let Some(upload) = upload.as_mut() {
+                            if upload.size() + chunk_data.len() as u64 > self.max_upload_size {
+                                return Ok(Response::new(self.too_large_ack()));
+                            }
+                            upload.write(&chunk_data).map_err(|e| {
                                 Status::internal(format!("Failed to write to file: {}", e))
                             })?;
                         } else {
@@ -192,10 +964,17 @@ impl AudioService for SDKAudioService {
             }
         }
 
-        Ok(Response::new(AudioAck {
-            success: Some(true),
-            error: None,
-        }))
+        let Some(upload) = upload else {
+            return Ok(Response::new(AudioAck {
+                success: Some(false),
+                error: Some(Error {
+                    details: "No file provided".to_string(),
+                }),
+            }));
+        };
+
+        let result = self.finish_upload(upload, None, self.conversion)?;
+        Ok(Response::new(result.ack.unwrap_or_default()))
     }
 
     type DownloadAudioFileStream = ReceiverStream<Result<AudioFileRequest, Status>>;
@@ -210,8 +989,7 @@ impl AudioService for SDKAudioService {
         );
 
         let name = request.into_inner().path;
-        let mut path = self.sounds_path.clone();
-        path.push(&name);
+        let path = self.library.resolve(&name)?;
 
         let mut file = File::open(&path)
             .map_err(|e| Status::internal(format!("Failed to open file: {}", e)))?;
@@ -264,12 +1042,24 @@ impl AudioService for SDKAudioService {
             request.remote_addr()
         );
 
-        let mut path = self.sounds_path.clone();
-        path.push(request.into_inner().path);
+        let name = request.into_inner().path;
+
+        if self.library.is_system(&name) {
+            return Ok(Response::new(AudioAck {
+                success: Some(false),
+                error: Some(Error {
+                    details: "System sounds are read-only".to_string(),
+                }),
+            }));
+        }
+        let path = self.library.writable(&name)?;
 
         if path.exists() {
-            fs::remove_file(path)
+            fs::remove_file(&path)
                 .map_err(|e| Status::internal(format!("Failed to remove file: {}", e)))?;
+            let name = self.library.relative(&path.to_string_lossy());
+            self.loudness.remove(&name);
+            self.tags.remove(&name);
         } else {
             return Ok(Response::new(AudioAck {
                 success: Some(false),
@@ -290,13 +1080,17 @@ impl AudioService for SDKAudioService {
             "Got a play_audio_file request from {:?}",
             request.remote_addr()
         );
-        let mut path = self.sounds_path.clone();
-        path.push(request.into_inner().path);
+        let path = self.library.resolve(&request.into_inner().path)?;
+        self.check_playable(&path)?;
 
         let _ = self
             .tx
             .send((
-                GstStatus::Play,
+                GstStatus::Play(
+                    self.next_id.fetch_add(1, Ordering::Relaxed),
+                    Some(1),
+                    self.fades,
+                ),
                 Some(path.to_str().unwrap().to_string()),
                 None,
             ))
@@ -319,15 +1113,17 @@ impl AudioService for SDKAudioService {
             request.remote_addr()
         );
         let audiofile = request.into_inner();
-        let mut path = self.sounds_path.clone();
-        path.push(audiofile.path);
+        let duration = to_recording_time(audiofile.duration)?;
+        let path = self.library.writable(&audiofile.path)?;
+        self.loudness
+            .remove(&self.library.relative(&path.to_string_lossy()));
 
         let _ = self
             .tx
             .send((
-                GstStatus::Record,
+                GstStatus::Record(RecordingOptions::default(), duration),
                 Some(path.to_str().unwrap().to_string()),
-                audiofile.duration,
+                None,
             ))
             .await;
         Ok(Response::new(()))
@@ -343,24 +1139,977 @@ impl AudioService for SDKAudioService {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    info!("Starting SDK Audio server");
-    env_logger::init();
-    gst::init().unwrap();
+#[tonic::async_trait]
+impl AudioExtService for SDKAudioService {
+    async fn play_audio(
+        &self,
+        request: Request<PlayRequest>,
+    ) -> Result<Response<PlaybackHandle>, Status> {
+        debug!("Got a play_audio request from {:?}", request.remote_addr());
+        let request = request.into_inner();
+        let Some(file) = request.file else {
+            return Err(Status::invalid_argument("No file provided"));
+        };
 
-    let args = Args::parse();
-    let grpc_address = format!("{}:{}", args.grpc_host, args.grpc_port)
-        .parse()
-        .unwrap();
+        let loops = if request.loop_forever {
+            None
+        } else {
+            match request.loop_count {
+                Some(0) => return Err(Status::invalid_argument("Loop count must be positive")),
+                loop_count => Some(loop_count.unwrap_or(1)),
+            }
+        };
 
-    //let addr = "[::1]:50063".parse().unwrap();
-    let audioservice = SDKAudioService::new().await;
+        let fades = Fades {
+            fade_in: to_duration(request.fade_in, self.fades.fade_in, "Fade duration")?,
+            fade_out: to_duration(request.fade_out, self.fades.fade_out, "Fade duration")?,
+        };
+
+        let path = self.library.resolve(&file.path)?;
+        self.check_playable(&path)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .tx
+            .send((
+                GstStatus::Play(id, loops, fades),
+                Some(path.to_str().unwrap().to_string()),
+                None,
+            ))
+            .await;
+        Ok(Response::new(PlaybackHandle { id }))
+    }
+
+    async fn stop_audio(&self, request: Request<PlaybackHandle>) -> Result<Response<()>, Status> {
+        debug!("Got a stop_audio request from {:?}", request.remote_addr());
+        let id = request.into_inner().id;
+        self.send_playback_command(|ack| GstStatus::Stop(id, ack), None)
+            .await?;
+        Ok(Response::new(()))
+    }
+
+    async fn set_playback_volume(
+        &self,
+        request: Request<PlaybackVolume>,
+    ) -> Result<Response<()>, Status> {
+        debug!(
+            "Got a set_playback_volume request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+        if !(0f32..=10f32).contains(&request.volume) {
+            return Err(Status::invalid_argument(
+                "Volume must be between 0.0 and 10.0",
+            ));
+        }
+        self.send_playback_command(
+            |ack| GstStatus::SetPlaybackVolume(request.id, ack),
+            Some(request.volume),
+        )
+        .await?;
+        Ok(Response::new(()))
+    }
+
+    async fn pause_audio(&self, request: Request<PlaybackTarget>) -> Result<Response<()>, Status> {
+        debug!("Got a pause_audio request from {:?}", request.remote_addr());
+        let id = request.into_inner().id;
+        self.send_playback_command(|ack| GstStatus::Pause(id, ack), None)
+            .await?;
+        Ok(Response::new(()))
+    }
+
+    async fn resume_audio(&self, request: Request<PlaybackTarget>) -> Result<Response<()>, Status> {
+        debug!(
+            "Got a resume_audio request from {:?}",
+            request.remote_addr()
+        );
+        let id = request.into_inner().id;
+        self.send_playback_command(|ack| GstStatus::Resume(id, ack), None)
+            .await?;
+        Ok(Response::new(()))
+    }
+
+    async fn seek_audio(&self, request: Request<SeekRequest>) -> Result<Response<()>, Status> {
+        debug!("Got a seek_audio request from {:?}", request.remote_addr());
+        let request = request.into_inner();
+        let position = to_duration(Some(request.position), Duration::ZERO, "Seek position")?;
+        self.send_playback_command(|ack| GstStatus::Seek(request.id, position, ack), None)
+            .await?;
+        Ok(Response::new(()))
+    }
+
+    async fn get_playback_position(
+        &self,
+        request: Request<PlaybackTarget>,
+    ) -> Result<Response<PlaybackPosition>, Status> {
+        debug!(
+            "Got a get_playback_position request from {:?}",
+            request.remote_addr()
+        );
+        let id = request.into_inner().id;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let _ = self
+            .tx
+            .send((GstStatus::Position(id, reply_tx), None, None))
+            .await;
+        let (position, duration) = reply_rx
+            .await
+            .map_err(|e| Status::internal(format!("Failed to query position: {}", e)))?
+            .ok_or_else(|| Status::not_found("Playback not found"))?;
+
+        Ok(Response::new(PlaybackPosition {
+            position: position.map(|p| p.as_secs_f32()),
+            duration: duration.map(|d| d.as_secs_f32()),
+        }))
+    }
+
+    async fn set_volume(&self, request: Request<Volume>) -> Result<Response<()>, Status> {
+        debug!("Got a set_volume request from {:?}", request.remote_addr());
+        let request = request.into_inner();
+
+        if let Some(volume) = request.volume {
+            if !(0f32..=10f32).contains(&volume) {
+                return Err(Status::invalid_argument(
+                    "Volume must be between 0.0 and 10.0",
+                ));
+            }
+            let _ = self
+                .tx
+                .send((GstStatus::SetVolume, None, Some(volume)))
+                .await;
+        }
+        if let Some(mute) = request.mute {
+            let _ = self.tx.send((GstStatus::Mute(mute), None, None)).await;
+        }
+        Ok(Response::new(()))
+    }
+
+    async fn get_volume(&self, request: Request<()>) -> Result<Response<Volume>, Status> {
+        debug!("Got a get_volume request from {:?}", request.remote_addr());
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let _ = self
+            .tx
+            .send((GstStatus::Volume(reply_tx), None, None))
+            .await;
+        let (volume, mute) = reply_rx
+            .await
+            .map_err(|e| Status::internal(format!("Failed to query volume: {}", e)))?;
+
+        Ok(Response::new(Volume {
+            volume: Some(volume),
+            mute: Some(mute),
+        }))
+    }
+
+    async fn get_playbacks(&self, request: Request<()>) -> Result<Response<Playbacks>, Status> {
+        debug!(
+            "Got a get_playbacks request from {:?}",
+            request.remote_addr()
+        );
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let _ = self
+            .tx
+            .send((GstStatus::Playbacks(reply_tx), None, None))
+            .await;
+        let playbacks = reply_rx
+            .await
+            .map_err(|e| Status::internal(format!("Failed to query playbacks: {}", e)))?;
+
+        Ok(Response::new(Playbacks {
+            playbacks: playbacks
+                .iter()
+                .map(|(id, path, playing)| self.to_playback(*id, path, *playing))
+                .collect(),
+        }))
+    }
+
+    async fn enqueue(
+        &self,
+        request: Request<AudioFile>,
+    ) -> Result<Response<PlaybackHandle>, Status> {
+        debug!("Got an enqueue request from {:?}", request.remote_addr());
+        let path = self.library.resolve(&request.into_inner().path)?;
+
+        if !path.exists() {
+            return Err(Status::not_found("File not found"));
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .tx
+            .send((
+                GstStatus::Enqueue(id),
+                Some(path.to_str().unwrap().to_string()),
+                None,
+            ))
+            .await;
+        Ok(Response::new(PlaybackHandle { id }))
+    }
+
+    async fn get_audio_file_info(
+        &self,
+        request: Request<AudioFile>,
+    ) -> Result<Response<AudioFileInfo>, Status> {
+        debug!(
+            "Got a get_audio_file_info request from {:?}",
+            request.remote_addr()
+        );
+        let file = request.into_inner();
+        let path = self.library.resolve(&file.path)?;
+
+        if !path.is_file() {
+            return Err(Status::not_found("File not found"));
+        }
+
+        let info = tokio::task::block_in_place(|| self.audio_info(&path))
+            .map_err(|e| Status::failed_precondition(format!("File cannot be decoded: {}", e)))?;
+        let name = self.library.relative(&path.to_string_lossy());
+
+        Ok(Response::new(AudioFileInfo {
+            file: Some(AudioFile {
+                path: file.path,
+                duration: info.duration.map(|d| d.as_secs_f32()),
+            }),
+            codec: info.codec,
+            container: info.container,
+            sample_rate: info.sample_rate,
+            channels: info.channels,
+            bitrate: info.bitrate,
+            tags: info.tags.into_iter().collect(),
+            loudness: self.loudness.loudness(&name).map(|l| l as f32),
+            gain: self.loudness.gain_db(&name) as f32,
+        }))
+    }
+
+    async fn skip(&self, request: Request<()>) -> Result<Response<()>, Status> {
+        debug!("Got a skip request from {:?}", request.remote_addr());
+        let _ = self.tx.send((GstStatus::Skip, None, None)).await;
+        Ok(Response::new(()))
+    }
+
+    async fn clear_queue(&self, request: Request<()>) -> Result<Response<()>, Status> {
+        debug!("Got a clear_queue request from {:?}", request.remote_addr());
+        let _ = self.tx.send((GstStatus::ClearQueue, None, None)).await;
+        Ok(Response::new(()))
+    }
+
+    async fn get_queue(&self, request: Request<()>) -> Result<Response<PlaybackQueue>, Status> {
+        debug!("Got a get_queue request from {:?}", request.remote_addr());
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let _ = self.tx.send((GstStatus::Queue(reply_tx), None, None)).await;
+        let (current, queued) = reply_rx
+            .await
+            .map_err(|e| Status::internal(format!("Failed to query queue: {}", e)))?;
+
+        // queued files are not playing yet
+        Ok(Response::new(PlaybackQueue {
+            current: current.map(|(id, path)| self.to_playback(id, &path, true)),
+            queued: queued
+                .iter()
+                .map(|(id, path)| self.to_playback(*id, path, false))
+                .collect(),
+        }))
+    }
+
+    async fn record_audio(&self, request: Request<RecordRequest>) -> Result<Response<()>, Status> {
+        debug!(
+            "Got a record_audio request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+        let Some(file) = request.file.as_ref() else {
+            return Err(Status::invalid_argument("No file provided"));
+        };
+        if request.bitrate == Some(0) {
+            return Err(Status::invalid_argument("Bitrate must be positive"));
+        }
+        if request.quality.is_some_and(|q| !(0f32..=1f32).contains(&q)) {
+            return Err(Status::invalid_argument(
+                "Quality must be between 0.0 and 1.0",
+            ));
+        }
+        let config = to_recording_config(request.config.unwrap_or_default())?;
+        let duration = to_recording_time(file.duration)?;
+
+        let options = RecordingOptions {
+            format: to_recording_format(request.format()),
+            bitrate: request.bitrate,
+            quality: request.quality,
+            config,
+        };
+
+        let path = self.library.writable(&file.path)?;
+        self.loudness
+            .remove(&self.library.relative(&path.to_string_lossy()));
+
+        let _ = self
+            .tx
+            .send((
+                GstStatus::Record(options, duration),
+                Some(path.to_str().unwrap().to_string()),
+                None,
+            ))
+            .await;
+        Ok(Response::new(()))
+    }
+
+    type StreamMicrophoneStream = ReceiverStream<Result<MicrophoneChunk, Status>>;
+
+    async fn stream_microphone(
+        &self,
+        request: Request<MicrophoneRequest>,
+    ) -> Result<Response<Self::StreamMicrophoneStream>, Status> {
+        debug!(
+            "Got a stream_microphone request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+        let config = to_recording_config(request.config.unwrap_or_default())?;
+        let encoding = match request.encoding() {
+            ext::StreamEncoding::Pcm => StreamEncoding::Pcm,
+            ext::StreamEncoding::Opus => StreamEncoding::Opus,
+        };
+        let chunk_duration = request.chunk_duration.unwrap_or(0.1f32);
+        if !(0.01f32..=10f32).contains(&chunk_duration) {
+            return Err(Status::invalid_argument(
+                "Chunk duration must be between 0.01 and 10.0",
+            ));
+        }
+
+        let (tx, rx) = mpsc::channel(16);
+
+        let chunk_tx = tx.clone();
+        let on_chunk: ChunkCallback = Box::new(move |chunk| {
+            // the capture goes on at its own pace, a slow client loses chunks
+            if let Err(mpsc::error::TrySendError::Full(_)) =
+                chunk_tx.try_send(Ok(to_microphone_chunk(chunk)))
+            {
+                warn!("Microphone stream lagging, chunk dropped");
+            }
+        });
+        let error_tx = tx.clone();
+        let on_event: EventCallback = Arc::new(move |event| {
+            if let GstEvent::Error { message, .. } = event {
+                let _ = error_tx.try_send(Err(Status::internal(message)));
+            }
+        });
+
+        let microphone = GstMicrophone::new(
+            config,
+            encoding,
+            Duration::from_secs_f32(chunk_duration),
+            on_chunk,
+            Some(on_event),
+        );
+        microphone.start();
+
+        // the capture stops once the client is gone
+        tokio::spawn(async move {
+            tx.closed().await;
+            drop(microphone);
+        });
+
+        Ok(Response::new(ReceiverStream::from(rx)))
+    }
+
+    async fn play_stream(
+        &self,
+        request: Request<tonic::Streaming<PlayStreamRequest>>,
+    ) -> Result<Response<PlaybackHandle>, Status> {
+        debug!("Got a play_stream request from {:?}", request.remote_addr());
+        let mut stream = request.into_inner();
+
+        let info = match stream.next().await {
+            Some(Ok(PlayStreamRequest {
+                data: Some(play_stream_request::Data::Info(info)),
+            })) => info,
+            Some(Err(e)) => return Err(e),
+            _ => return Err(Status::invalid_argument("Stream info expected first")),
+        };
+        let pcm = match info.encoding() {
+            ext::StreamEncoding::Pcm => {
+                let config = to_recording_config(info.config.unwrap_or_default())?;
+                if config.rate.is_none() || config.channels.is_none() {
+                    return Err(Status::invalid_argument(
+                        "Sample rate and channel count are required for PCM",
+                    ));
+                }
+                Some(config)
+            }
+            ext::StreamEncoding::Opus => None,
+        };
+
+        let first_chunk = loop {
+            match stream.next().await {
+                Some(Ok(request)) => {
+                    if let Some(play_stream_request::Data::ChunkData(chunk)) = request.data {
+                        break chunk;
+                    }
+                }
+                Some(Err(e)) => return Err(e),
+                None => return Err(Status::invalid_argument("No audio data received")),
+            }
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let _ = self
+            .tx
+            .send((
+                GstStatus::PlayStream(id, pcm, first_chunk, reply_tx),
+                Some(info.name),
+                None,
+            ))
+            .await;
+        let source = reply_rx
+            .await
+            .map_err(|e| Status::internal(format!("Failed to start stream: {}", e)))?
+            .map_err(Status::failed_precondition)?;
+
+        let result = loop {
+            match stream.next().await {
+                Some(Ok(request)) => {
+                    if let Some(play_stream_request::Data::ChunkData(chunk)) = request.data {
+                        // waits for the player to catch up, which slows the client down
+                        if !tokio::task::block_in_place(|| source.push(&chunk)) {
+                            debug!("Streamed playback {} stopped", id);
+                            break Ok(());
+                        }
+                    }
+                }
+                Some(Err(e)) => break Err(e),
+                None => break Ok(()),
+            }
+        };
+        source.end();
+        result?;
+
+        Ok(Response::new(PlaybackHandle { id }))
+    }
+
+    type WatchAudioEventsStream = ReceiverStream<Result<AudioEvent, Status>>;
+
+    async fn watch_audio_events(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<Self::WatchAudioEventsStream>, Status> {
+        debug!(
+            "Got a watch_audio_events request from {:?}",
+            request.remote_addr()
+        );
+
+        let mut events = self.events.subscribe();
+        let library = self.library.clone();
+        let (tx, rx) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok((id, path, event)) => {
+                        let event = to_audio_event(&library, id, &path, event);
+                        if tx.send(Ok(event)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("Audio event watcher lagging, {} events dropped", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::from(rx)))
+    }
+
+    async fn upload_audio(
+        &self,
+        request: Request<tonic::Streaming<UploadRequest>>,
+    ) -> Result<Response<UploadResult>, Status> {
+        debug!(
+            "Got an upload_audio request from {:?}",
+            request.remote_addr()
+        );
+
+        let mut stream = request.into_inner();
+        let info = match stream.next().await {
+            Some(Ok(UploadRequest {
+                data: Some(upload_request::Data::Info(info)),
+            })) => info,
+            Some(Err(e)) => return Err(e),
+            _ => return Err(Status::invalid_argument("No upload info provided")),
+        };
+        let Some(file) = info.file else {
+            return Err(Status::invalid_argument("No file provided"));
+        };
+        let path = self.library.writable(&file.path)?;
+        let conversion = match info.transcode {
+            None => self.conversion,
+            Some(options) => {
+                let format = to_recording_format(options.format());
+                let config = to_recording_config(options.config.unwrap_or_default())?;
+                format.map(|format| (format, config))
+            }
+        };
+
+        if info
+            .total_size
+            .is_some_and(|size| size > self.max_upload_size)
+        {
+            return Ok(Response::new(self.too_large_result()));
+        }
+        let received = PartialFile::offset(&path);
+        if info.offset > received {
+            return Err(Status::out_of_range(format!(
+                "Offset {} beyond the {} bytes received",
+                info.offset, received
+            )));
+        }
+        let mut upload = PartialFile::resume(path, info.offset)
+            .map_err(|e| Status::internal(format!("Failed to open file: {}", e)))?;
+
+        // the received bytes are kept when the stream is interrupted
+        while let Some(request) = stream.next().await {
+            let chunk = match request?.data {
+                Some(upload_request::Data::ChunkData(chunk)) => chunk,
+                _ => {
+                    return Err(Status::invalid_argument(
+                        "Only chunks can follow the upload info",
+                    ))
+                }
+            };
+            let size = upload.size() + chunk.len() as u64;
+            if size > self.max_upload_size {
+                upload.discard();
+                return Ok(Response::new(self.too_large_result()));
+            }
+            if info.total_size.is_some_and(|total_size| size > total_size) {
+                upload.discard();
+                return Err(Status::invalid_argument("More data than the total size"));
+            }
+            upload
+                .write(&chunk)
+                .map_err(|e| Status::internal(format!("Failed to write to file: {}", e)))?;
+        }
+
+        if let Some(total_size) = info.total_size {
+            if upload.size() < total_size {
+                return Ok(Response::new(UploadResult {
+                    ack: Some(failed_ack(format!(
+                        "Upload incomplete, {} of {} bytes received",
+                        upload.size(),
+                        total_size
+                    ))),
+                    file: None,
+                }));
+            }
+        }
+
+        Ok(Response::new(self.finish_upload(
+            upload,
+            info.sha256.as_deref(),
+            conversion,
+        )?))
+    }
+
+    async fn get_upload_offset(
+        &self,
+        request: Request<AudioFile>,
+    ) -> Result<Response<UploadOffset>, Status> {
+        debug!(
+            "Got a get_upload_offset request from {:?}",
+            request.remote_addr()
+        );
+        let path = self.library.writable(&request.into_inner().path)?;
+
+        Ok(Response::new(UploadOffset {
+            offset: PartialFile::offset(&path),
+        }))
+    }
+
+    type DownloadAudioStream = ReceiverStream<Result<DownloadChunk, Status>>;
+
+    async fn download_audio(
+        &self,
+        request: Request<DownloadRequest>,
+    ) -> Result<Response<Self::DownloadAudioStream>, Status> {
+        debug!(
+            "Got a download_audio request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+        let Some(file) = request.file.as_ref() else {
+            return Err(Status::invalid_argument("No file provided"));
+        };
+        let path = self.library.resolve(&file.path)?;
+
+        if !path.is_file() {
+            return Err(Status::not_found("File not found"));
+        }
+
+        let mut file = File::open(&path)
+            .map_err(|e| Status::internal(format!("Failed to open file: {}", e)))?;
+        let total_size = file
+            .metadata()
+            .map_err(|e| Status::internal(format!("Failed to read file: {}", e)))?
+            .len();
+        if request.offset > total_size {
+            return Err(Status::out_of_range(format!(
+                "Offset {} beyond the end of the file, {} bytes long",
+                request.offset, total_size
+            )));
+        }
+        file.seek(SeekFrom::Start(request.offset))
+            .map_err(|e| Status::internal(format!("Failed to read file: {}", e)))?;
+        let length = request
+            .length
+            .map_or(total_size - request.offset, |length| {
+                length.min(total_size - request.offset)
+            });
+        let mut file = file.take(length);
+        let mut sha256 = tokio::task::block_in_place(|| self.checksum(&path))
+            .map_err(|e| Status::internal(format!("Failed to read file: {}", e)))?;
+
+        let mut offset = request.offset;
+        let (tx, rx) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let n = match file.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) => {
+                        let _ = tx
+                            .send(Err(Status::internal(format!("Failed to read file: {}", e))))
+                            .await;
+                        break;
+                    }
+                };
+
+                // the checksum is only sent with the first chunk
+                let chunk = DownloadChunk {
+                    offset,
+                    data: buffer[..n].to_vec(),
+                    total_size,
+                    sha256: std::mem::take(&mut sha256),
+                };
+                if tx.send(Ok(chunk)).await.is_err() {
+                    break;
+                }
+                offset += n as u64;
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::from(rx)))
+    }
+
+    async fn analyse_loudness(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<LoudnessReport>, Status> {
+        debug!(
+            "Got an analyse_loudness request from {:?}",
+            request.remote_addr()
+        );
+
+        let files = tokio::task::block_in_place(|| {
+            self.library
+                .list()
+                .into_iter()
+                .map(|(name, path)| {
+                    let (loudness, error) = match self.loudness.analyse(&name, &path) {
+                        Ok(loudness) => (loudness, None),
+                        Err(e) => (None, Some(e.to_string())),
+                    };
+                    FileLoudness {
+                        gain: self.loudness.gain_db(&name) as f32,
+                        file: Some(self.to_audio_file(name, &path)),
+                        loudness: loudness.map(|l| l as f32),
+                        error,
+                    }
+                })
+                .collect()
+        });
+
+        Ok(Response::new(LoudnessReport { files }))
+    }
+
+    async fn get_audio_files(
+        &self,
+        request: Request<ListRequest>,
+    ) -> Result<Response<SoundFiles>, Status> {
+        debug!(
+            "Got an ext get_audio_files request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+
+        let files = tokio::task::block_in_place(|| self.list_sound_files(&request));
+        Ok(Response::new(SoundFiles { files }))
+    }
+
+    async fn rename_audio_file(
+        &self,
+        request: Request<FileTransfer>,
+    ) -> Result<Response<AudioAck>, Status> {
+        debug!(
+            "Got a rename_audio_file request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+        let overwrite = request.overwrite();
+        let (Some(source), Some(destination)) = (request.source, request.destination) else {
+            return Err(Status::invalid_argument(
+                "Source and destination are required",
+            ));
+        };
+
+        if self.library.is_system(&source.path) {
+            return Ok(Response::new(failed_ack(
+                "System sounds are read-only".to_string(),
+            )));
+        }
+        let from = self.library.resolve(&source.path)?;
+        if !from.is_file() {
+            return Ok(Response::new(failed_ack(
+                "Source file not found".to_string(),
+            )));
+        }
+        let to = self.library.writable(&destination.path)?;
+        if to.is_dir() {
+            return Ok(Response::new(failed_ack(
+                "Destination is a directory".to_string(),
+            )));
+        }
+        if to.exists() && to != from {
+            if let Some(ack) = existing_destination_ack(overwrite) {
+                return Ok(Response::new(ack));
+            }
+        }
+
+        fs::rename(&from, &to)
+            .map_err(|e| Status::internal(format!("Failed to rename file: {}", e)))?;
+        let from_name = self.library.relative(&from.to_string_lossy());
+        let to_name = self.library.relative(&to.to_string_lossy());
+        self.loudness.rename(&from_name, &to_name);
+        self.tags.rename(&from_name, &to_name);
+
+        Ok(Response::new(AudioAck {
+            success: Some(true),
+            error: None,
+        }))
+    }
+
+    async fn copy_audio_file(
+        &self,
+        request: Request<FileTransfer>,
+    ) -> Result<Response<AudioAck>, Status> {
+        debug!(
+            "Got a copy_audio_file request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+        let overwrite = request.overwrite();
+        let (Some(source), Some(destination)) = (request.source, request.destination) else {
+            return Err(Status::invalid_argument(
+                "Source and destination are required",
+            ));
+        };
+
+        let from = self.library.resolve(&source.path)?;
+        if !from.is_file() {
+            return Ok(Response::new(failed_ack(
+                "Source file not found".to_string(),
+            )));
+        }
+        let to = self.library.writable(&destination.path)?;
+        if to.is_dir() {
+            return Ok(Response::new(failed_ack(
+                "Destination is a directory".to_string(),
+            )));
+        }
+        if to.exists() {
+            if let Some(ack) = existing_destination_ack(overwrite) {
+                return Ok(Response::new(ack));
+            }
+        }
+
+        // copied next to the destination first, so that a replaced file stays whole
+        let copy = PartialFile::create(to.clone())
+            .map_err(|e| Status::internal(format!("Failed to create file: {}", e)))?;
+        tokio::task::block_in_place(|| fs::copy(&from, copy.temp_path()))
+            .map_err(|e| Status::internal(format!("Failed to copy file: {}", e)))?;
+        copy.commit()
+            .map_err(|e| Status::internal(format!("Failed to save file: {}", e)))?;
+        let from_name = self.library.relative(&from.to_string_lossy());
+        let to_name = self.library.relative(&to.to_string_lossy());
+        self.loudness.copy(&from_name, &to_name);
+        self.tags.copy(&from_name, &to_name);
+
+        Ok(Response::new(AudioAck {
+            success: Some(true),
+            error: None,
+        }))
+    }
+
+    async fn set_audio_tags(&self, request: Request<AudioTags>) -> Result<Response<()>, Status> {
+        debug!(
+            "Got a set_audio_tags request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+        let Some(file) = request.file else {
+            return Err(Status::invalid_argument("No file provided"));
+        };
+        let path = self.library.resolve(&file.path)?;
+
+        if !path.is_file() {
+            return Err(Status::not_found("File not found"));
+        }
+        // an empty set removes the tags of the file
+        let tags: Tags = request.tags.into_iter().collect();
+        self.tags.set(
+            &self.library.relative(&path.to_string_lossy()),
+            (!tags.is_empty()).then_some(tags),
+        );
+        Ok(Response::new(()))
+    }
+
+    async fn get_audio_tags(
+        &self,
+        request: Request<AudioFile>,
+    ) -> Result<Response<AudioTags>, Status> {
+        debug!(
+            "Got a get_audio_tags request from {:?}",
+            request.remote_addr()
+        );
+        let file = request.into_inner();
+        let path = self.library.resolve(&file.path)?;
+
+        if !path.is_file() {
+            return Err(Status::not_found("File not found"));
+        }
+        let tags = self
+            .tags
+            .get(&self.library.relative(&path.to_string_lossy()));
+        Ok(Response::new(AudioTags {
+            file: Some(file),
+            tags: tags.unwrap_or_default().into_iter().collect(),
+        }))
+    }
+
+    async fn create_directory(&self, request: Request<Directory>) -> Result<Response<()>, Status> {
+        debug!(
+            "Got a create_directory request from {:?}",
+            request.remote_addr()
+        );
+        let path = self.library.directory(&request.into_inner().path)?;
+
+        if path.exists() {
+            return Err(Status::already_exists("Path already exists"));
+        }
+        fs::create_dir_all(&path)
+            .map_err(|e| Status::internal(format!("Failed to create directory: {}", e)))?;
+        Ok(Response::new(()))
+    }
+
+    async fn remove_directory(
+        &self,
+        request: Request<RemoveDirectoryRequest>,
+    ) -> Result<Response<()>, Status> {
+        debug!(
+            "Got a remove_directory request from {:?}",
+            request.remote_addr()
+        );
+        let request = request.into_inner();
+        let Some(directory) = request.directory else {
+            return Err(Status::invalid_argument("No directory provided"));
+        };
+        let path = self.library.directory(&directory.path)?;
+
+        if !path.is_dir() {
+            return Err(Status::not_found("Directory not found"));
+        }
+        if request.recursive {
+            fs::remove_dir_all(&path)
+                .map_err(|e| Status::internal(format!("Failed to remove directory: {}", e)))?;
+            let name = self.library.relative(&path.to_string_lossy());
+            self.loudness.remove_dir(&name);
+            self.tags.remove_dir(&name);
+        } else {
+            let is_empty = fs::read_dir(&path)
+                .map_err(|e| Status::internal(format!("Failed to read directory: {}", e)))?
+                .next()
+                .is_none();
+            if !is_empty {
+                return Err(Status::failed_precondition("Directory not empty"));
+            }
+            fs::remove_dir(&path)
+                .map_err(|e| Status::internal(format!("Failed to remove directory: {}", e)))?;
+        }
+        Ok(Response::new(()))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    info!("Starting SDK Audio server");
+    env_logger::init();
+    gst::init().unwrap();
+
+    let args = Args::parse();
+    let grpc_address = format!("{}:{}", args.grpc_host, args.grpc_port)
+        .parse()
+        .unwrap();
+
+    //let addr = "[::1]:50063".parse().unwrap();
+    let sounds_dir = args.sounds_dir.unwrap_or_else(|| {
+        let mut sounds_dir = env::temp_dir();
+        sounds_dir.push("Reachy_SDK_audio_server");
+        sounds_dir
+    });
+    let library = SoundLibrary::new(sounds_dir, args.system_sounds_dir)
+        .map_err(|e| format!("Invalid sounds directory: {}", e))?;
+    info!("Sounds stored in {}", library.user_dir().display());
+
+    let conversion = args.transcode_format.map(|format| {
+        let config = RecordingConfig {
+            rate: Some(args.transcode_rate),
+            channels: Some(args.transcode_channels),
+            sample_format: None,
+        };
+        (format, config)
+    });
+
+    let loudness = LoudnessStore::load(
+        library.user_dir().join(".loudness.json"),
+        args.target_loudness,
+    );
+    let tags_file = library.user_dir().join(".tags.json");
+
+    let fades = Fades {
+        fade_in: Duration::from_millis(args.fade_in_ms),
+        fade_out: Duration::from_millis(args.fade_out_ms),
+    };
+    let audioservice = Arc::new(
+        SDKAudioService::new(
+            library,
+            fades,
+            Duration::from_millis(args.crossfade_ms),
+            args.max_upload_mb * 1024 * 1024,
+            conversion,
+            loudness,
+            TagStore::load(tags_file),
+        )
+        .await,
+    );
 
     info!("AudioService listening on {}", grpc_address);
 
     Server::builder()
-        .add_service(AudioServiceServer::new(audioservice))
+        .add_service(AudioServiceServer::from_arc(audioservice.clone()))
+        .add_service(AudioExtServiceServer::from_arc(audioservice))
         .serve(grpc_address)
         .await?;
 
@@ -0,0 +1,313 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use tonic::Status;
+
+// every extension the recorder writes has to be listed, see RecordingFormat::from_extension
+const SOUND_EXTENSIONS: &[&str] = &["mp3", "wav", "ogg", "opus", "flac"];
+
+/// Sound files of the server: a writable user directory, and an optional read-only
+/// directory of system sounds listed along with it.
+#[derive(Clone, Debug)]
+pub struct SoundLibrary {
+    user_dir: PathBuf,
+    system_dir: Option<PathBuf>,
+}
+
+impl SoundLibrary {
+    /// Creates the user directory if needed, and checks that both directories can be used.
+    pub fn new(user_dir: PathBuf, system_dir: Option<PathBuf>) -> io::Result<Self> {
+        fs::create_dir_all(&user_dir)?;
+        let user_dir = user_dir.canonicalize()?;
+        let system_dir = system_dir.map(|dir| dir.canonicalize()).transpose()?;
+        if fs::metadata(&user_dir)?.permissions().readonly() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is read-only", user_dir.display()),
+            ));
+        }
+        if let Some(system_dir) = system_dir.as_ref() {
+            if !fs::metadata(system_dir)?.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a directory", system_dir.display()),
+                ));
+            }
+        }
+
+        Ok(Self {
+            user_dir,
+            system_dir,
+        })
+    }
+
+    pub fn user_dir(&self) -> &Path {
+        &self.user_dir
+    }
+
+    /// Path of a file to read, the user files shadowing the system ones.
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, Status> {
+        let user_path = join(&self.user_dir, name)?;
+        if !user_path.exists() {
+            if let Some(system_dir) = self.system_dir.as_ref() {
+                let system_path = join(system_dir, name)?;
+                if system_path.exists() {
+                    return Ok(system_path);
+                }
+            }
+        }
+        Ok(user_path)
+    }
+
+    /// Path of a file to create or modify, always in the user directory.
+    /// The directory holding it has to exist.
+    pub fn writable(&self, name: &str) -> Result<PathBuf, Status> {
+        let path = join(&self.user_dir, name)?;
+        if !path.parent().is_some_and(|dir| dir.is_dir()) {
+            return Err(Status::not_found(format!(
+                "Directory of {} not found",
+                name
+            )));
+        }
+        Ok(path)
+    }
+
+    /// Path of a directory of the user sounds, the sounds directory itself excluded.
+    pub fn directory(&self, name: &str) -> Result<PathBuf, Status> {
+        join(&self.user_dir, name)
+    }
+
+    /// True if the file only exists among the system sounds. The user directory does not
+    /// need to have its directory.
+    pub fn is_system(&self, name: &str) -> bool {
+        matches!(
+            (self.resolve(name), join(&self.user_dir, name)),
+            (Ok(path), Ok(user_path)) if path != user_path
+        )
+    }
+
+    /// Name of the file at `path`, relative to the directory holding it.
+    pub fn relative(&self, path: &str) -> String {
+        let path = Path::new(path);
+        std::iter::once(&self.user_dir)
+            .chain(self.system_dir.as_ref())
+            .find_map(|dir| path.strip_prefix(dir).ok())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Sound files of both directories and their subdirectories, named by their path
+    /// relative to them. A user file hides the system one of the same name. Hidden files,
+    /// such as uploads in progress, are left out.
+    pub fn list(&self) -> Vec<(String, PathBuf)> {
+        let mut files = Vec::new();
+        let mut names = HashSet::new();
+
+        for root in std::iter::once(&self.user_dir).chain(self.system_dir.as_ref()) {
+            let mut found = Vec::new();
+            list_dir(root, &mut found);
+            for path in found {
+                let name = path
+                    .strip_prefix(root)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .to_string();
+                if names.insert(name.clone()) {
+                    files.push((name, path));
+                }
+            }
+        }
+        files
+    }
+}
+
+/// Adds the sound files of `dir` and of its subdirectories to `files`.
+fn list_dir(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        // symbolic links to directories are not followed, they could lead to a loop
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            list_dir(&path, files);
+            continue;
+        }
+        let is_sound = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| SOUND_EXTENSIONS.contains(&e));
+        if is_sound {
+            files.push(path);
+        }
+    }
+}
+
+/// File being uploaded, written under a hidden name next to its destination until it is
+/// complete. It is removed if dropped before being committed, unless it is resumable.
+pub struct PartialFile {
+    file: File,
+    temp_path: PathBuf,
+    path: PathBuf,
+    size: u64,
+    committed: bool,
+    resumable: bool,
+}
+
+impl PartialFile {
+    /// File written in one go, under another hidden name than the resumable uploads so
+    /// that an interrupted upload to the same path is left alone.
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        let temp_path = temp_path(&path, "new");
+        Self::with_temp_path(path, temp_path)
+    }
+
+    /// Same as `create`, under another hidden name so that the file can be converted
+    /// from an upload to the same path.
+    pub fn create_converted(path: PathBuf) -> io::Result<Self> {
+        let temp_path = temp_path(&path, "converted");
+        Self::with_temp_path(path, temp_path)
+    }
+
+    fn with_temp_path(path: PathBuf, temp_path: PathBuf) -> io::Result<Self> {
+        let file = File::create(&temp_path)?;
+
+        Ok(Self {
+            file,
+            temp_path,
+            path,
+            size: 0,
+            committed: false,
+            resumable: false,
+        })
+    }
+
+    /// Continues an interrupted upload from `offset`, dropping the bytes received after it.
+    /// The file is kept when dropped, so that the upload can be resumed again.
+    pub fn resume(path: PathBuf, offset: u64) -> io::Result<Self> {
+        let temp_path = temp_path(&path, "part");
+        let mut file = if offset == 0 {
+            File::create(&temp_path)?
+        } else {
+            OpenOptions::new().write(true).open(&temp_path)?
+        };
+        if offset > file.metadata()?.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offset beyond the received bytes",
+            ));
+        }
+        file.set_len(offset)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            file,
+            temp_path,
+            path,
+            size: offset,
+            committed: false,
+            resumable: true,
+        })
+    }
+
+    /// Number of bytes received by an interrupted upload to `path`.
+    pub fn offset(path: &Path) -> u64 {
+        fs::metadata(temp_path(path, "part")).map_or(0, |m| m.len())
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Number of bytes written so far.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Destination of the file once committed.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// Moves the complete file to its destination, replacing any previous file.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Removes the file, even if it is resumable.
+    pub fn discard(mut self) {
+        self.resumable = false;
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if !self.committed && !self.resumable {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Hex encoded SHA-256 of the file.
+pub fn sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn temp_path(path: &Path, suffix: &str) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}", file_name, suffix))
+}
+
+/// Path of `name` in `dir`, rejecting absolute paths, parent components and symbolic
+/// links leading out of the directory. Hidden names are rejected too, the stores and the
+/// partial uploads being kept under such names.
+fn join(dir: &Path, name: &str) -> Result<PathBuf, Status> {
+    let invalid = || Status::invalid_argument(format!("Invalid sound path: {:?}", name));
+
+    let relative = Path::new(name);
+    let is_plain = relative.components().all(|c| match c {
+        Component::Normal(part) => !part.to_string_lossy().starts_with('.'),
+        Component::CurDir => true,
+        _ => false,
+    });
+    if !is_plain || relative.file_name().is_none() {
+        return Err(invalid());
+    }
+
+    let path = dir.join(relative);
+    // The file may not exist yet, so check the deepest existing ancestor instead. Links
+    // are not followed to find it, a dangling one failing to canonicalize.
+    let existing = path
+        .ancestors()
+        .find(|p| p.symlink_metadata().is_ok())
+        .and_then(|p| p.canonicalize().ok());
+    match existing {
+        Some(existing) if existing.starts_with(dir) => Ok(path),
+        _ => Err(invalid()),
+    }
+}
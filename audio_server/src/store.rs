@@ -0,0 +1,99 @@
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Values attached to the sound files, kept in a JSON file of the sounds directory. The
+/// files are named by their path relative to the library, as listed by it.
+pub struct JsonStore<V> {
+    file: PathBuf,
+    values: Mutex<BTreeMap<String, V>>,
+}
+
+impl<V: Clone + Serialize + DeserializeOwned> JsonStore<V> {
+    /// Reads the values stored in `file`, starting empty if there are none.
+    pub fn load(file: PathBuf) -> Self {
+        let values = match fs::read(&file) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!("Ignoring invalid file {}: {}", file.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        Self {
+            file,
+            values: Mutex::new(values),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<V> {
+        self.values.lock().unwrap().get(name).cloned()
+    }
+
+    /// Replaces the value of the file, None removing it.
+    pub fn set(&self, name: &str, value: Option<V>) {
+        let mut values = self.values.lock().unwrap();
+        match value {
+            Some(value) => values.insert(name.to_string(), value),
+            None => values.remove(name),
+        };
+        self.save(&values);
+    }
+
+    /// Moves the value of a renamed file.
+    pub fn rename(&self, from: &str, to: &str) {
+        self.transfer(from, to, false);
+    }
+
+    /// Gives a copy the value of its source.
+    pub fn copy(&self, from: &str, to: &str) {
+        self.transfer(from, to, true);
+    }
+
+    fn transfer(&self, from: &str, to: &str, keep_source: bool) {
+        let mut values = self.values.lock().unwrap();
+        let value = if keep_source {
+            values.get(from).cloned()
+        } else {
+            values.remove(from)
+        };
+        match value {
+            Some(value) => values.insert(to.to_string(), value),
+            // the replaced file may have had one
+            None => values.remove(to),
+        };
+        self.save(&values);
+    }
+
+    /// Drops the value of a removed file.
+    pub fn remove(&self, name: &str) {
+        let mut values = self.values.lock().unwrap();
+        if values.remove(name).is_some() {
+            self.save(&values);
+        }
+    }
+
+    /// Drops the values of the files of a removed directory.
+    pub fn remove_dir(&self, dir: &str) {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let mut values = self.values.lock().unwrap();
+        let count = values.len();
+        values.retain(|name, _| !name.starts_with(&prefix));
+        if values.len() != count {
+            self.save(&values);
+        }
+    }
+
+    fn save(&self, values: &BTreeMap<String, V>) {
+        let result = serde_json::to_vec_pretty(values)
+            .map_err(|e| e.to_string())
+            .and_then(|data| fs::write(&self.file, data).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!("Failed to save {}: {}", self.file.display(), e);
+        }
+    }
+}
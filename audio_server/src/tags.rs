@@ -0,0 +1,21 @@
+use crate::store::JsonStore;
+use std::collections::{BTreeMap, HashMap};
+
+pub type Tags = BTreeMap<String, String>;
+
+/// User tags of the sound files, such as their emotion or language.
+pub type TagStore = JsonStore<Tags>;
+
+impl TagStore {
+    /// True if the file has all the tags of `filter`, with the same values.
+    pub fn matches(&self, name: &str, filter: &HashMap<String, String>) -> bool {
+        if filter.is_empty() {
+            return true;
+        }
+        self.get(name).is_some_and(|tags| {
+            filter
+                .iter()
+                .all(|(key, value)| tags.get(key) == Some(value))
+        })
+    }
+}
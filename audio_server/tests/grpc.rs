@@ -1,9 +1,19 @@
 use reachy_api::component::audio::audio_service_client::AudioServiceClient;
+use reachy_api::component::audio::ext::audio_ext_service_client::AudioExtServiceClient;
+use reachy_api::component::audio::ext::{
+    play_stream_request, upload_request, AudioFormat, AudioTags, Directory, DownloadRequest,
+    FileTransfer, ListRequest, OverwritePolicy, PlayRequest, PlayStreamRequest, PlaybackHandle,
+    PlaybackTarget, RecordRequest, RecordingConfig, RemoveDirectoryRequest, TranscodeOptions,
+    UploadInfo, UploadRequest,
+};
 use reachy_api::component::audio::AudioFile;
-use reachy_api::component::audio::{audio_file_request, AudioFileRequest};
+use reachy_api::component::audio::{audio_file_request, AudioAck, AudioFileRequest};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::path::PathBuf;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
@@ -219,3 +229,1312 @@ async fn test_remove_file() {
     assert!(ack.success.unwrap());
     assert!(ack.error.is_none());
 }
+
+#[tokio::test]
+async fn test_queue() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let audiofile = AudioFile {
+        path: "dummy".to_string(),
+        duration: None,
+    };
+
+    let status = client.enqueue(audiofile).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    client.clear_queue(()).await.unwrap();
+
+    let queue = client.get_queue(()).await.unwrap().into_inner();
+    assert!(queue.queued.is_empty());
+}
+
+#[tokio::test]
+async fn test_play_invalid_loop_count() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let request = PlayRequest {
+        file: Some(AudioFile {
+            path: "dummy".to_string(),
+            duration: None,
+        }),
+        loop_count: Some(0),
+        loop_forever: false,
+        fade_in: None,
+        fade_out: None,
+    };
+
+    let status = client.play_audio(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_play_not_found() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let audiofile = AudioFile {
+        path: "dummy.ogg".to_string(),
+        duration: None,
+    };
+
+    let status = client.play_audio_file(audiofile.clone()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    let request = PlayRequest {
+        file: Some(audiofile),
+        loop_count: None,
+        loop_forever: false,
+        fade_in: None,
+        fade_out: None,
+    };
+    let status = ext_client.play_audio(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    let playbacks = ext_client.get_playbacks(()).await.unwrap().into_inner();
+    assert!(playbacks
+        .playbacks
+        .iter()
+        .all(|p| p.file.as_ref().unwrap().path != "dummy.ogg"));
+}
+
+#[tokio::test]
+async fn test_unknown_playback() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let status = client
+        .stop_audio(PlaybackHandle { id: u64::MAX })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    let status = client
+        .pause_audio(PlaybackTarget { id: Some(u64::MAX) })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_record_invalid_quality() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let request = RecordRequest {
+        file: Some(AudioFile {
+            path: "dummy.mp3".to_string(),
+            duration: Some(1.0f32),
+        }),
+        format: 0,
+        bitrate: None,
+        quality: Some(2.0f32),
+        config: None,
+    };
+
+    let status = client.record_audio(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_record_invalid_config() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let request = RecordRequest {
+        file: Some(AudioFile {
+            path: "dummy.wav".to_string(),
+            duration: Some(1.0f32),
+        }),
+        format: 0,
+        bitrate: None,
+        quality: None,
+        config: Some(RecordingConfig {
+            rate: Some(16000),
+            channels: Some(0),
+            sample_format: 0,
+        }),
+    };
+
+    let status = client.record_audio(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_record_invalid_duration() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    for duration in [-1f32, f32::NAN, f32::INFINITY] {
+        let audiofile = AudioFile {
+            path: "dummy.wav".to_string(),
+            duration: Some(duration),
+        };
+
+        let status = client
+            .record_audio_file(audiofile.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = ext_client
+            .record_audio(RecordRequest {
+                file: Some(audiofile),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    // the recordings can still be stopped after the rejected requests
+    client.stop_recording(()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_play_stream_no_info() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let (tx, rx) = mpsc::channel(1);
+
+    tx.send(PlayStreamRequest {
+        data: Some(play_stream_request::Data::ChunkData(vec![0; 64])),
+    })
+    .await
+    .expect("Failed to send chunk");
+    drop(tx);
+
+    let status = client
+        .play_stream(ReceiverStream::new(rx))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_audio_file_info_not_found() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let audiofile = AudioFile {
+        path: "dummy.ogg".to_string(),
+        duration: None,
+    };
+
+    let status = client.get_audio_file_info(audiofile).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_audio_file_info() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let original_data = read_sample();
+
+    let name = "unit_test_info.ogg";
+    let ack = upload_part(
+        &mut ext_client,
+        name,
+        &original_data,
+        0,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(ack.success.unwrap());
+
+    let files = client.get_audio_files(()).await.unwrap().into_inner().files;
+    let uploaded = files
+        .iter()
+        .find(|f| f.path == name)
+        .expect("Uploaded file not listed");
+    assert!(uploaded.duration.unwrap() > 0f32);
+
+    let info = ext_client
+        .get_audio_file_info(uploaded.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!info.codec.is_empty());
+    assert!(info.sample_rate > 0);
+    assert!(info.channels > 0);
+    assert_eq!(info.file.unwrap().duration, uploaded.duration);
+
+    client.remove_audio_file(uploaded.clone()).await.unwrap();
+
+    // an empty file cannot be decoded
+    let empty_file_name = "unit_test_empty.ogg";
+    let mut path = env::temp_dir();
+    path.push("Reachy_SDK_audio_server");
+    std::fs::create_dir_all(&path).unwrap();
+    path.push(empty_file_name);
+    File::create(&path).unwrap();
+
+    let status = ext_client
+        .get_audio_file_info(AudioFile {
+            path: empty_file_name.to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+fn outside_sounds_dir() -> Vec<AudioFile> {
+    ["../../etc/passwd", "/etc/passwd", "..", ""]
+        .iter()
+        .map(|path| AudioFile {
+            path: path.to_string(),
+            duration: None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_upload_outside_sounds_dir() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    for audiofile in outside_sounds_dir() {
+        let (tx, rx) = mpsc::channel(2);
+        tx.send(AudioFileRequest {
+            data: Some(audio_file_request::Data::Info(audiofile)),
+        })
+        .await
+        .expect("Failed to send file name");
+        tx.send(AudioFileRequest {
+            data: Some(audio_file_request::Data::ChunkData(vec![0; 64])),
+        })
+        .await
+        .expect("Failed to send chunk");
+        drop(tx);
+
+        let status = client
+            .upload_audio_file(ReceiverStream::new(rx))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
+
+#[tokio::test]
+async fn test_download_outside_sounds_dir() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    for audiofile in outside_sounds_dir() {
+        let status = client.download_audio_file(audiofile).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
+
+#[tokio::test]
+async fn test_remove_outside_sounds_dir() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    for audiofile in outside_sounds_dir() {
+        let status = client.remove_audio_file(audiofile).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
+
+#[tokio::test]
+async fn test_play_outside_sounds_dir() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    for audiofile in outside_sounds_dir() {
+        let status = client.play_audio_file(audiofile.clone()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = ext_client
+            .play_audio(PlayRequest {
+                file: Some(audiofile.clone()),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = ext_client.enqueue(audiofile.clone()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = ext_client.get_audio_file_info(audiofile).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
+
+#[tokio::test]
+async fn test_record_outside_sounds_dir() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    for audiofile in outside_sounds_dir() {
+        let status = client
+            .record_audio_file(audiofile.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = ext_client
+            .record_audio(RecordRequest {
+                file: Some(audiofile),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
+
+#[tokio::test]
+async fn test_upload_undecodable_file() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let unit_file_name = "unit_test_garbage.ogg";
+    let (tx, rx) = mpsc::channel(2);
+    tx.send(AudioFileRequest {
+        data: Some(audio_file_request::Data::Info(AudioFile {
+            path: unit_file_name.to_string(),
+            duration: None,
+        })),
+    })
+    .await
+    .expect("Failed to send file name");
+    tx.send(AudioFileRequest {
+        data: Some(audio_file_request::Data::ChunkData(vec![42; 1024])),
+    })
+    .await
+    .expect("Failed to send chunk");
+    drop(tx);
+
+    let response = client
+        .upload_audio_file(ReceiverStream::new(rx))
+        .await
+        .expect("Failed to upload file");
+    let ack = response.into_inner();
+    assert!(!ack.success.unwrap());
+    assert!(ack.error.is_some());
+
+    // neither the file nor its partial upload is left in the sounds directory
+    let response = client.get_audio_files(()).await.unwrap();
+    assert!(!is_file_in_list(
+        response.into_inner().files,
+        unit_file_name
+    ));
+
+    let mut path = env::temp_dir();
+    path.push("Reachy_SDK_audio_server");
+    assert!(!path.join(unit_file_name).exists());
+    assert!(!path.join(format!(".{}.part", unit_file_name)).exists());
+}
+
+/// Content of the sample file of the repository.
+fn read_sample() -> Vec<u8> {
+    let mut file_path = env::current_dir().unwrap();
+    file_path.push("../data/");
+    file_path.push("sample-3.ogg");
+    let mut data = Vec::new();
+    File::open(&file_path)
+        .expect("Failed to open file")
+        .read_to_end(&mut data)
+        .expect("Failed to read file");
+    data
+}
+
+async fn upload_part(
+    client: &mut AudioExtServiceClient<tonic::transport::Channel>,
+    name: &str,
+    data: &[u8],
+    offset: usize,
+    total_size: usize,
+    sha256: Option<String>,
+) -> AudioAck {
+    let mut requests = vec![UploadRequest {
+        data: Some(upload_request::Data::Info(UploadInfo {
+            file: Some(AudioFile {
+                path: name.to_string(),
+                duration: None,
+            }),
+            offset: offset as u64,
+            total_size: Some(total_size as u64),
+            sha256,
+            transcode: None,
+        })),
+    }];
+    requests.extend(data.chunks(16 * 1024).map(|chunk| UploadRequest {
+        data: Some(upload_request::Data::ChunkData(chunk.to_vec())),
+    }));
+
+    client
+        .upload_audio(tokio_stream::iter(requests))
+        .await
+        .expect("Failed to upload file")
+        .into_inner()
+        .ack
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_resumable_upload_download() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let original_data = read_sample();
+
+    let unit_file_name = "unit_test_resumed.ogg";
+    let audiofile = AudioFile {
+        path: unit_file_name.to_string(),
+        duration: None,
+    };
+    let half = original_data.len() / 2;
+
+    // the first half is kept once the stream ends
+    let ack = upload_part(
+        &mut client,
+        unit_file_name,
+        &original_data[..half],
+        0,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(!ack.success.unwrap());
+    let offset = client
+        .get_upload_offset(audiofile.clone())
+        .await
+        .unwrap()
+        .into_inner()
+        .offset;
+    assert_eq!(offset, half as u64);
+
+    // resuming beyond the received bytes is refused
+    let status = client
+        .upload_audio(tokio_stream::iter(vec![UploadRequest {
+            data: Some(upload_request::Data::Info(UploadInfo {
+                file: Some(audiofile.clone()),
+                offset: half as u64 + 1,
+                total_size: Some(original_data.len() as u64),
+                sha256: None,
+                transcode: None,
+            })),
+        }]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::OutOfRange);
+
+    // a one-shot upload to the same path leaves the interrupted upload alone
+    let mut audio_client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let ack = audio_client
+        .upload_audio_file(tokio_stream::iter(vec![
+            AudioFileRequest {
+                data: Some(audio_file_request::Data::Info(audiofile.clone())),
+            },
+            AudioFileRequest {
+                data: Some(audio_file_request::Data::ChunkData(vec![42; 1024])),
+            },
+        ]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!ack.success.unwrap());
+    let offset = client
+        .get_upload_offset(audiofile.clone())
+        .await
+        .unwrap()
+        .into_inner()
+        .offset;
+    assert_eq!(offset, half as u64);
+
+    let ack = upload_part(
+        &mut client,
+        unit_file_name,
+        &original_data[half..],
+        half,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(ack.success.unwrap());
+    assert!(ack.error.is_none());
+    let offset = client
+        .get_upload_offset(audiofile.clone())
+        .await
+        .unwrap()
+        .into_inner()
+        .offset;
+    assert_eq!(offset, 0);
+
+    // a range in the middle of the file
+    let mut stream = client
+        .download_audio(DownloadRequest {
+            file: Some(audiofile.clone()),
+            offset: half as u64,
+            length: Some(1000),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut received_data = Vec::new();
+    while let Some(chunk) = stream.message().await.unwrap() {
+        assert_eq!(chunk.offset, (half + received_data.len()) as u64);
+        assert_eq!(chunk.total_size, original_data.len() as u64);
+        received_data.extend(chunk.data);
+    }
+    assert_eq!(received_data, original_data[half..half + 1000]);
+
+    let status = client
+        .download_audio(DownloadRequest {
+            file: Some(audiofile.clone()),
+            offset: original_data.len() as u64 + 1,
+            length: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::OutOfRange);
+
+    let mut audio_client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let ack = audio_client
+        .remove_audio_file(audiofile)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+}
+
+#[tokio::test]
+async fn test_upload_download_checksum() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let original_data = read_sample();
+    let sha256 = format!("{:x}", Sha256::digest(&original_data));
+
+    let unit_file_name = "unit_test_checksum.ogg";
+    let audiofile = AudioFile {
+        path: unit_file_name.to_string(),
+        duration: None,
+    };
+
+    let ack = upload_part(
+        &mut client,
+        unit_file_name,
+        &original_data,
+        0,
+        original_data.len(),
+        Some("0".repeat(64)),
+    )
+    .await;
+    assert!(!ack.success.unwrap());
+    assert!(ack.error.is_some());
+    let files = client
+        .get_audio_files(ListRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .files;
+    assert!(!files
+        .iter()
+        .any(|f| f.file.as_ref().unwrap().path == unit_file_name));
+
+    let ack = upload_part(
+        &mut client,
+        unit_file_name,
+        &original_data,
+        0,
+        original_data.len(),
+        Some(sha256.clone()),
+    )
+    .await;
+    assert!(ack.success.unwrap());
+
+    let files = client
+        .get_audio_files(ListRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .files;
+    let listed = files
+        .iter()
+        .find(|f| f.file.as_ref().unwrap().path == unit_file_name)
+        .expect("Uploaded file not listed");
+    assert_eq!(listed.sha256, sha256);
+    assert_eq!(listed.size, original_data.len() as u64);
+
+    let mut stream = client
+        .download_audio(DownloadRequest {
+            file: Some(audiofile.clone()),
+            offset: 0,
+            length: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let first_chunk = stream.message().await.unwrap().unwrap();
+    assert_eq!(first_chunk.sha256, sha256);
+
+    let mut audio_client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let ack = audio_client
+        .remove_audio_file(audiofile)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+}
+
+#[tokio::test]
+async fn test_upload_transcode() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let original_data = read_sample();
+
+    let mut requests = vec![UploadRequest {
+        data: Some(upload_request::Data::Info(UploadInfo {
+            file: Some(AudioFile {
+                path: "unit_test_transcoded.ogg".to_string(),
+                duration: None,
+            }),
+            offset: 0,
+            total_size: None,
+            sha256: None,
+            transcode: Some(TranscodeOptions {
+                format: AudioFormat::Wav.into(),
+                config: Some(RecordingConfig {
+                    rate: Some(16000),
+                    channels: Some(1),
+                    ..Default::default()
+                }),
+            }),
+        })),
+    }];
+    requests.extend(original_data.chunks(16 * 1024).map(|chunk| UploadRequest {
+        data: Some(upload_request::Data::ChunkData(chunk.to_vec())),
+    }));
+
+    let result = client
+        .upload_audio(tokio_stream::iter(requests.clone()))
+        .await
+        .expect("Failed to upload file")
+        .into_inner();
+    assert!(result.ack.unwrap().success.unwrap());
+    let file = result.file.expect("No file in the upload result");
+    assert_eq!(file.path, "unit_test_transcoded.wav");
+    assert!(file.duration.unwrap() > 0f32);
+
+    let info = client
+        .get_audio_file_info(file.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(info.sample_rate, 16000);
+    assert_eq!(info.channels, 1);
+
+    // an existing file is not replaced by the conversion of another upload
+    let result = client
+        .upload_audio(tokio_stream::iter(requests))
+        .await
+        .expect("Failed to upload file")
+        .into_inner();
+    assert!(!result.ack.unwrap().success.unwrap());
+
+    let mut audio_client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let ack = audio_client
+        .remove_audio_file(file)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+}
+
+#[tokio::test]
+async fn test_analyse_loudness() {
+    gst::init().unwrap();
+    if gst::ElementFactory::find("ebur128level").is_none() {
+        println!("ebur128level element not found, skipping the loudness analysis test");
+        return;
+    }
+
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let original_data = read_sample();
+
+    let unit_file_name = "unit_test_loudness.ogg";
+    let audiofile = AudioFile {
+        path: unit_file_name.to_string(),
+        duration: None,
+    };
+
+    let ack = upload_part(
+        &mut client,
+        unit_file_name,
+        &original_data,
+        0,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(ack.success.unwrap());
+
+    let report = client.analyse_loudness(()).await.unwrap().into_inner();
+    let analysed = report
+        .files
+        .iter()
+        .find(|f| f.file.as_ref().unwrap().path == unit_file_name)
+        .expect("Uploaded file not analysed");
+    assert!(analysed.error.is_none());
+    assert!(analysed.gain <= 12f32);
+
+    // the measure is kept for the playback
+    let info = client
+        .get_audio_file_info(audiofile.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    let loudness = info.loudness.expect("Analysed file has no loudness");
+    assert!(loudness < 0f32);
+    assert!((analysed.loudness.unwrap() - loudness).abs() < 0.1);
+    assert_eq!(analysed.gain, info.gain);
+
+    // the measure is dropped once the file is replaced, uploads not being analysed
+    // without a target loudness
+    let ack = upload_part(
+        &mut client,
+        unit_file_name,
+        &original_data,
+        0,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(ack.success.unwrap());
+    let info = client
+        .get_audio_file_info(audiofile.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(info.loudness.is_none());
+
+    let mut audio_client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let ack = audio_client
+        .remove_audio_file(audiofile)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+}
+
+#[tokio::test]
+async fn test_sound_directories() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let original_data = read_sample();
+
+    let directory = Directory {
+        path: "unit_test_dir/nested".to_string(),
+    };
+    let unit_file_name = "unit_test_dir/nested/sample.ogg";
+
+    // the directory has to exist before uploading into it
+    let status = client
+        .get_upload_offset(AudioFile {
+            path: unit_file_name.to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    client.create_directory(directory.clone()).await.unwrap();
+    let status = client
+        .create_directory(directory.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::AlreadyExists);
+    let status = client
+        .create_directory(Directory {
+            path: "../unit_test_dir".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    let ack = upload_part(
+        &mut client,
+        unit_file_name,
+        &original_data,
+        0,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(ack.success.unwrap());
+
+    let files = client
+        .get_audio_files(ListRequest {
+            prefix: "unit_test_dir/".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .files;
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].file.as_ref().unwrap().path, unit_file_name);
+
+    let status = client
+        .remove_directory(RemoveDirectoryRequest {
+            directory: Some(Directory {
+                path: "unit_test_dir".to_string(),
+            }),
+            recursive: false,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    client
+        .remove_directory(RemoveDirectoryRequest {
+            directory: Some(Directory {
+                path: "unit_test_dir".to_string(),
+            }),
+            recursive: true,
+        })
+        .await
+        .unwrap();
+    let files = client
+        .get_audio_files(ListRequest {
+            prefix: "unit_test_dir/".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .files;
+    assert!(files.is_empty());
+}
+
+#[tokio::test]
+async fn test_hidden_files() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let data = read_sample();
+    let name = "unit_test_hidden.ogg";
+    let ack = upload_part(&mut ext_client, name, &data, 0, data.len(), None).await;
+    assert!(ack.success.unwrap());
+
+    // the stores and the partial uploads are out of reach
+    for hidden in [".tags.json", ".loudness.json", ".unit_test_hidden.ogg.part"] {
+        let hidden_file = AudioFile {
+            path: hidden.to_string(),
+            duration: None,
+        };
+        let status = client
+            .remove_audio_file(hidden_file.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = client.download_audio_file(hidden_file).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = ext_client
+            .rename_audio_file(file_transfer(
+                hidden,
+                "unit_test_hidden_moved.ogg",
+                OverwritePolicy::Fail,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = ext_client
+            .copy_audio_file(file_transfer(name, hidden, OverwritePolicy::Replace))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    client
+        .remove_audio_file(AudioFile {
+            path: name.to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap();
+}
+
+fn file_transfer(source: &str, destination: &str, overwrite: OverwritePolicy) -> FileTransfer {
+    FileTransfer {
+        source: Some(AudioFile {
+            path: source.to_string(),
+            duration: None,
+        }),
+        destination: Some(AudioFile {
+            path: destination.to_string(),
+            duration: None,
+        }),
+        overwrite: overwrite.into(),
+    }
+}
+
+#[tokio::test]
+async fn test_rename_copy_file() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let original_data = read_sample();
+
+    let source = "unit_test_source.ogg";
+    let copy = "unit_test_copy.ogg";
+    let renamed = "unit_test_renamed.ogg";
+    let ack = upload_part(
+        &mut client,
+        source,
+        &original_data,
+        0,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(ack.success.unwrap());
+
+    let ack = client
+        .copy_audio_file(file_transfer(source, copy, OverwritePolicy::Fail))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+
+    // the destination now exists
+    let ack = client
+        .copy_audio_file(file_transfer(source, copy, OverwritePolicy::Fail))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!ack.success.unwrap());
+    assert!(ack.error.is_some());
+    let ack = client
+        .copy_audio_file(file_transfer(source, copy, OverwritePolicy::Skip))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+
+    let ack = client
+        .rename_audio_file(file_transfer("dummy.ogg", renamed, OverwritePolicy::Fail))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!ack.success.unwrap());
+    assert!(ack.error.is_some());
+
+    let ack = client
+        .rename_audio_file(file_transfer(copy, renamed, OverwritePolicy::Fail))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+    let ack = client
+        .rename_audio_file(file_transfer(source, renamed, OverwritePolicy::Fail))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!ack.success.unwrap());
+    let ack = client
+        .rename_audio_file(file_transfer(source, renamed, OverwritePolicy::Replace))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+
+    let status = client
+        .rename_audio_file(file_transfer(
+            renamed,
+            "../renamed.ogg",
+            OverwritePolicy::Fail,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    let files = client
+        .get_audio_files(ListRequest {
+            prefix: "unit_test_".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .files;
+    let names: Vec<_> = files
+        .iter()
+        .map(|f| f.file.as_ref().unwrap().path.as_str())
+        .collect();
+    assert!(names.contains(&renamed));
+    assert!(!names.contains(&source));
+    assert!(!names.contains(&copy));
+
+    let mut audio_client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let ack = audio_client
+        .remove_audio_file(AudioFile {
+            path: renamed.to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+}
+
+async fn tagged_files(
+    client: &mut AudioExtServiceClient<tonic::transport::Channel>,
+    tags: &[(&str, &str)],
+) -> Vec<String> {
+    client
+        .get_audio_files(ListRequest {
+            prefix: "unit_test_".to_string(),
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .files
+        .into_iter()
+        .map(|f| f.file.unwrap().path)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_audio_tags() {
+    let mut client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let original_data = read_sample();
+
+    let name = "unit_test_tagged.ogg";
+    let renamed = "unit_test_tagged_renamed.ogg";
+    let ack = upload_part(
+        &mut client,
+        name,
+        &original_data,
+        0,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(ack.success.unwrap());
+
+    let tags: HashMap<String, String> = [("emotion", "happy"), ("language", "en")]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    client
+        .set_audio_tags(AudioTags {
+            file: Some(AudioFile {
+                path: name.to_string(),
+                duration: None,
+            }),
+            tags: tags.clone(),
+        })
+        .await
+        .unwrap();
+
+    let read_tags = client
+        .get_audio_tags(AudioFile {
+            path: name.to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(read_tags.tags, tags);
+
+    let status = client
+        .get_audio_tags(AudioFile {
+            path: "dummy.ogg".to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    let files = tagged_files(&mut client, &[("emotion", "happy")]).await;
+    assert_eq!(files, vec![name.to_string()]);
+    let files = tagged_files(&mut client, &[("emotion", "happy"), ("language", "fr")]).await;
+    assert!(files.is_empty());
+
+    // the tags follow the file
+    let ack = client
+        .rename_audio_file(file_transfer(name, renamed, OverwritePolicy::Fail))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+    let files = tagged_files(&mut client, &[("language", "en")]).await;
+    assert_eq!(files, vec![renamed.to_string()]);
+
+    let mut audio_client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let ack = audio_client
+        .remove_audio_file(AudioFile {
+            path: renamed.to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+
+    // a new file of the same name starts untagged
+    let ack = upload_part(
+        &mut client,
+        renamed,
+        &original_data,
+        0,
+        original_data.len(),
+        None,
+    )
+    .await;
+    assert!(ack.success.unwrap());
+    let read_tags = client
+        .get_audio_tags(AudioFile {
+            path: renamed.to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(read_tags.tags.is_empty());
+    let ack = audio_client
+        .remove_audio_file(AudioFile {
+            path: renamed.to_string(),
+            duration: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ack.success.unwrap());
+}
+
+#[tokio::test]
+async fn test_nested_system_sound() {
+    // the server has to share the system sounds directory with the test
+    let Ok(system_dir) = env::var("REACHY_SYSTEM_SOUNDS_DIR") else {
+        println!("REACHY_SYSTEM_SOUNDS_DIR unset, skipping");
+        return;
+    };
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    // no such directory among the user sounds
+    let mut dir = PathBuf::from(system_dir);
+    dir.push("unit_test_system_dir");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut path = dir.clone();
+    path.push("unit_test_system.ogg");
+    File::create(&path).unwrap();
+    let name = "unit_test_system_dir/unit_test_system.ogg";
+    let audiofile = AudioFile {
+        path: name.to_string(),
+        duration: None,
+    };
+
+    let files = client.get_audio_files(()).await.unwrap().into_inner().files;
+    assert!(is_file_in_list(files, name));
+
+    let ack = client
+        .remove_audio_file(audiofile)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!ack.success.unwrap());
+    assert!(ack.error.is_some());
+
+    let ack = ext_client
+        .rename_audio_file(file_transfer(
+            name,
+            "unit_test_system_renamed.ogg",
+            OverwritePolicy::Fail,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!ack.success.unwrap());
+    assert!(ack.error.is_some());
+    assert!(path.exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -1,7 +1,17 @@
 use reachy_api::component::audio::audio_service_client::AudioServiceClient;
-use reachy_api::component::audio::AudioFile;
+use reachy_api::component::audio::ext::audio_ext_service_client::AudioExtServiceClient;
+use reachy_api::component::audio::ext::{
+    audio_event, play_stream_request, AudioFormat, MicrophoneRequest, PlayRequest,
+    PlayStreamRequest, PlaybackHandle, PlaybackTarget, PlaybackVolume, RecordRequest,
+    RecordingConfig, SampleFormat, SeekRequest, StreamInfo, Volume,
+};
+use reachy_api::component::audio::{audio_file_request, AudioFile, AudioFileRequest};
 
-use std::{thread, time::Duration};
+use std::process::{Child, Command};
+use std::{env, fs, thread, time::Duration};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Channel;
 
 #[tokio::test]
 async fn test_playback_recording() {
@@ -34,3 +44,553 @@ async fn test_playback_recording() {
 
     client.remove_audio_file(audiofile).await.unwrap();
 }
+
+/// Uploads the sample file of the repository under `name`.
+async fn upload_sample(client: &mut AudioServiceClient<Channel>, name: &str) -> AudioFile {
+    let mut file_path = env::current_dir().unwrap();
+    file_path.push("../data/");
+    file_path.push("sample-3.ogg");
+    let data = fs::read(&file_path).expect("Failed to read file");
+
+    let audiofile = AudioFile {
+        path: name.to_string(),
+        duration: None,
+    };
+    let mut requests = vec![AudioFileRequest {
+        data: Some(audio_file_request::Data::Info(audiofile.clone())),
+    }];
+    requests.extend(data.chunks(64 * 1024).map(|chunk| AudioFileRequest {
+        data: Some(audio_file_request::Data::ChunkData(chunk.to_vec())),
+    }));
+
+    let ack = client
+        .upload_audio_file(tokio_stream::iter(requests))
+        .await
+        .expect("Failed to upload file")
+        .into_inner();
+    assert!(ack.success.unwrap());
+    audiofile
+}
+
+/// Server started with options of its own for a test, killed when dropped.
+struct ServerProcess(Child);
+
+impl ServerProcess {
+    /// Starts the server on `port` with `args` and waits until it accepts connections.
+    async fn start(port: u16, args: &[&str]) -> Self {
+        let child = Command::new(env!("CARGO_BIN_EXE_reachy2_sdk_audio_server_rs"))
+            .args(["--grpc-port", &port.to_string()])
+            .args(args)
+            .spawn()
+            .expect("Failed to start server");
+        let server = Self(child);
+        for _ in 0..50 {
+            if AudioServiceClient::connect(format!("http://0.0.0.0:{}", port))
+                .await
+                .is_ok()
+            {
+                return server;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        panic!("Server did not start on port {}", port);
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn play_request(file: &AudioFile) -> PlayRequest {
+    PlayRequest {
+        file: Some(file.clone()),
+        loop_count: None,
+        loop_forever: false,
+        fade_in: None,
+        fade_out: None,
+    }
+}
+
+#[tokio::test]
+async fn test_pause_resume() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let audiofile = upload_sample(&mut client, "test_SDK_pause.ogg").await;
+    let handle = ext_client
+        .play_audio(play_request(&audiofile))
+        .await
+        .unwrap()
+        .into_inner();
+
+    thread::sleep(Duration::from_secs(1));
+
+    println!("pausing playback for 1 sec");
+    ext_client
+        .pause_audio(PlaybackTarget {
+            id: Some(handle.id),
+        })
+        .await
+        .unwrap();
+    let playbacks = ext_client.get_playbacks(()).await.unwrap().into_inner();
+    assert!(playbacks
+        .playbacks
+        .iter()
+        .any(|p| p.id == handle.id && !p.playing));
+
+    thread::sleep(Duration::from_secs(1));
+    ext_client
+        .resume_audio(PlaybackTarget {
+            id: Some(handle.id),
+        })
+        .await
+        .unwrap();
+    let playbacks = ext_client.get_playbacks(()).await.unwrap().into_inner();
+    assert!(playbacks
+        .playbacks
+        .iter()
+        .any(|p| p.id == handle.id && p.playing));
+
+    ext_client.stop_audio(handle).await.unwrap();
+    client.remove_audio_file(audiofile).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_seek_position() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let audiofile = upload_sample(&mut client, "test_SDK_seek.ogg").await;
+    let handle = ext_client
+        .play_audio(play_request(&audiofile))
+        .await
+        .unwrap()
+        .into_inner();
+
+    thread::sleep(Duration::from_secs(2));
+
+    let position = ext_client
+        .get_playback_position(PlaybackTarget {
+            id: Some(handle.id),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(position.position.unwrap() > 1f32);
+    assert!(position.duration.unwrap() > 0f32);
+
+    println!("rewinding to the beginning");
+    ext_client
+        .seek_audio(SeekRequest {
+            position: 0f32,
+            id: Some(handle.id),
+        })
+        .await
+        .unwrap();
+    let position = ext_client
+        .get_playback_position(PlaybackTarget {
+            id: Some(handle.id),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(position.position.unwrap() < 1f32);
+
+    for position in [-1f32, f32::NAN, f32::INFINITY, f32::MAX] {
+        let status = ext_client
+            .seek_audio(SeekRequest {
+                position,
+                id: Some(handle.id),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    // the playback is still reachable after the rejected positions
+    ext_client
+        .seek_audio(SeekRequest {
+            position: 0f32,
+            id: Some(handle.id),
+        })
+        .await
+        .unwrap();
+
+    ext_client.stop_audio(handle).await.unwrap();
+    client.remove_audio_file(audiofile).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_volume() {
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    println!("lowering volume");
+    ext_client
+        .set_volume(Volume {
+            volume: Some(0.5f32),
+            mute: None,
+        })
+        .await
+        .unwrap();
+    let volume = ext_client.get_volume(()).await.unwrap().into_inner();
+    assert_eq!(volume.volume, Some(0.5f32));
+    assert_eq!(volume.mute, Some(false));
+
+    ext_client
+        .set_volume(Volume {
+            volume: None,
+            mute: Some(true),
+        })
+        .await
+        .unwrap();
+    let volume = ext_client.get_volume(()).await.unwrap().into_inner();
+    assert_eq!(volume.volume, Some(0.5f32));
+    assert_eq!(volume.mute, Some(true));
+
+    let status = ext_client
+        .set_volume(Volume {
+            volume: Some(11f32),
+            mute: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    ext_client
+        .set_volume(Volume {
+            volume: Some(1f32),
+            mute: Some(false),
+        })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_mix_playbacks() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let audiofile = upload_sample(&mut client, "test_SDK_mix.ogg").await;
+
+    println!("mixing two playbacks of the sample");
+    let first = ext_client
+        .play_audio(play_request(&audiofile))
+        .await
+        .unwrap()
+        .into_inner();
+    thread::sleep(Duration::from_secs(1));
+    let second = ext_client
+        .play_audio(play_request(&audiofile))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_ne!(first.id, second.id);
+
+    thread::sleep(Duration::from_secs(1));
+    let playbacks = ext_client.get_playbacks(()).await.unwrap().into_inner();
+    for handle in [first, second] {
+        assert!(playbacks
+            .playbacks
+            .iter()
+            .any(|p| p.id == handle.id && p.playing));
+        ext_client.stop_audio(handle).await.unwrap();
+    }
+
+    client.remove_audio_file(audiofile).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_playback_handles() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let audiofile = upload_sample(&mut client, "test_SDK_handle.ogg").await;
+    let handle = ext_client
+        .play_audio(play_request(&audiofile))
+        .await
+        .unwrap()
+        .into_inner();
+    ext_client
+        .set_playback_volume(PlaybackVolume {
+            id: handle.id,
+            volume: 0.5f32,
+        })
+        .await
+        .unwrap();
+
+    let playbacks = ext_client.get_playbacks(()).await.unwrap().into_inner();
+    let playback = playbacks
+        .playbacks
+        .iter()
+        .find(|p| p.id == handle.id)
+        .expect("Playback not listed");
+    assert_eq!(playback.file.as_ref().unwrap().path, audiofile.path);
+
+    thread::sleep(Duration::from_secs(1));
+    ext_client
+        .pause_audio(PlaybackTarget {
+            id: Some(handle.id),
+        })
+        .await
+        .unwrap();
+    let id = handle.id;
+    ext_client.stop_audio(handle).await.unwrap();
+
+    let status = ext_client
+        .stop_audio(PlaybackHandle { id })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    client.remove_audio_file(audiofile).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_recording_events() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let unit_file_name = "test_SDK_events.ogg";
+
+    let mut events = ext_client
+        .watch_audio_events(())
+        .await
+        .unwrap()
+        .into_inner();
+
+    let audiofile = AudioFile {
+        path: unit_file_name.to_string(),
+        duration: Some(2.0f32),
+    };
+
+    println!("recording for 2 seconds");
+    client.record_audio_file(audiofile.clone()).await.unwrap();
+
+    let finalized = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(event) = events.message().await.unwrap() {
+            if event.path != unit_file_name {
+                continue;
+            }
+            if let Some(audio_event::Event::RecordingFinalized(recording)) = event.event {
+                return Some(recording);
+            }
+        }
+        None
+    })
+    .await
+    .expect("No recording event received");
+
+    assert!(finalized.unwrap().size > 0);
+
+    client.remove_audio_file(audiofile).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_record_wav() {
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let audiofile = AudioFile {
+        path: "test_SDK_recording.wav".to_string(),
+        duration: Some(1.0f32),
+    };
+
+    println!("recording 1 second of 16 kHz mono wav");
+    ext_client
+        .record_audio(RecordRequest {
+            file: Some(audiofile.clone()),
+            format: AudioFormat::Wav.into(),
+            bitrate: None,
+            quality: None,
+            config: Some(RecordingConfig {
+                rate: Some(16000),
+                channels: Some(1),
+                sample_format: SampleFormat::S16.into(),
+            }),
+        })
+        .await
+        .unwrap();
+
+    thread::sleep(Duration::from_secs(2));
+
+    let files = client.get_audio_files(()).await.unwrap().into_inner();
+    assert!(files.files.iter().any(|f| f.path == audiofile.path));
+
+    // the header gives the size of the complete recording
+    let info = ext_client
+        .get_audio_file_info(audiofile.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(info.sample_rate, 16000);
+    assert_eq!(info.channels, 1);
+    assert!(info.file.unwrap().duration.unwrap() > 0.5f32);
+
+    client.remove_audio_file(audiofile).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_stream_microphone() {
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let mut stream = ext_client
+        .stream_microphone(MicrophoneRequest {
+            config: Some(RecordingConfig {
+                rate: Some(16000),
+                channels: Some(1),
+                sample_format: SampleFormat::S16.into(),
+            }),
+            encoding: 0,
+            chunk_duration: Some(0.1f32),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    println!("receiving 1 second of microphone audio");
+    for _ in 0..10 {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), stream.message())
+            .await
+            .expect("No microphone chunk received")
+            .unwrap()
+            .unwrap();
+        let config = chunk.config.unwrap();
+        assert_eq!(config.rate, Some(16000));
+        assert_eq!(config.channels, Some(1));
+        // at least 0.1 s of 16 bit samples
+        assert!(chunk.data.len() >= 3200);
+    }
+}
+
+#[tokio::test]
+async fn test_play_stream() {
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50063")
+        .await
+        .expect("Failed to connect to server. Make sure that server is running for this test!");
+
+    let mut events = ext_client
+        .watch_audio_events(())
+        .await
+        .unwrap()
+        .into_inner();
+
+    let (tx, rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        tx.send(PlayStreamRequest {
+            data: Some(play_stream_request::Data::Info(StreamInfo {
+                config: Some(RecordingConfig {
+                    rate: Some(16000),
+                    channels: Some(1),
+                    sample_format: SampleFormat::S16.into(),
+                }),
+                encoding: 0,
+                name: "test_SDK_stream".to_string(),
+            })),
+        })
+        .await
+        .expect("Failed to send stream info");
+
+        // 2 seconds of silence in 0.1 s chunks
+        for _ in 0..20 {
+            tx.send(PlayStreamRequest {
+                data: Some(play_stream_request::Data::ChunkData(vec![0; 3200])),
+            })
+            .await
+            .expect("Failed to send chunk");
+        }
+    });
+
+    println!("streaming 2 seconds of silence");
+    let handle = ext_client
+        .play_stream(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(handle.id > 0);
+
+    // the handle is the one of a playback that did start
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(event) = events.message().await.unwrap() {
+            if event.id == Some(handle.id)
+                && matches!(event.event, Some(audio_event::Event::Started(_)))
+            {
+                return;
+            }
+        }
+        panic!("Event stream closed");
+    })
+    .await
+    .expect("Streamed playback not started");
+}
+
+#[tokio::test]
+async fn test_crossfade_without_watcher() {
+    let _server = ServerProcess::start(50064, &["--crossfade-ms", "1000"]).await;
+    let mut client = AudioServiceClient::connect("http://0.0.0.0:50064")
+        .await
+        .unwrap();
+    let mut ext_client = AudioExtServiceClient::connect("http://0.0.0.0:50064")
+        .await
+        .unwrap();
+
+    let first = upload_sample(&mut client, "test_SDK_crossfade_1.ogg").await;
+    let second = upload_sample(&mut client, "test_SDK_crossfade_2.ogg").await;
+    ext_client.enqueue(first.clone()).await.unwrap();
+    let next = ext_client
+        .enqueue(second.clone())
+        .await
+        .unwrap()
+        .into_inner();
+
+    // nobody watches the events, the next file still starts as soon as the crossfade does
+    let mut started = false;
+    for _ in 0..200 {
+        let queue = ext_client.get_queue(()).await.unwrap().into_inner();
+        if queue.current.as_ref().is_some_and(|p| p.id == next.id) {
+            started = true;
+            break;
+        }
+        assert!(
+            queue.current.is_some() || queue.queued.is_empty(),
+            "queue stalled during the crossfade"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(started);
+
+    client.stop_playing(()).await.unwrap();
+    client.remove_audio_file(first).await.unwrap();
+    client.remove_audio_file(second).await.unwrap();
+}
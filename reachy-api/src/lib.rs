@@ -6,5 +6,8 @@ pub mod component {
     tonic::include_proto!("component");
     pub mod audio {
         tonic::include_proto!("component.audio");
+        pub mod ext {
+            tonic::include_proto!("component.audio.ext");
+        }
     }
 }